@@ -12,18 +12,39 @@ struct Args {
 enum Command {
     /// archspec command line interface for CPU
     Cpu,
+
+    /// Print which version of the archspec-json data is used
+    Data,
 }
 
 fn main() {
     let args = Args::parse();
     match args.command {
         Command::Cpu => detect_cpu(),
+        Command::Data => print_data_version(),
     }
 }
 
 fn detect_cpu() {
     match archspec::cpu::host() {
         Ok(arch) => println!("{}", arch.name()),
-        Err(_err) => eprintln!("Error: unsupported micro architecture"),
+        Err(err) => eprintln!("Error: {}", err),
     }
 }
+
+fn print_data_version() {
+    match archspec::schema::cpu_dir() {
+        Some(dir) => println!("archspec-json source: {}", dir.display()),
+        None => println!("archspec-json source: embedded"),
+    }
+
+    // The version is only known for the embedded files, which are unused if a directory is set.
+    let version = archspec::schema::embedded_json_version();
+    println!(
+        "embedded archspec-json commit: {}",
+        version
+            .commit
+            .unwrap_or("unknown (only recorded in builds from a git checkout)")
+    );
+    println!("embedded archspec-json checksum: {}", version.checksum);
+}
@@ -0,0 +1,303 @@
+//! Generates static tables from the embedded archspec-json files, so that the default schemas do
+//! not have to be parsed at runtime, and records which version of the files is embedded. See
+//! `src/schema/embedded.rs` for the types of the tables. The target graph is not generated, it is
+//! still built from the schemas when the default database is first used.
+
+use serde_json::{Map, Value};
+use std::fmt::Write;
+use std::path::Path;
+use std::process::Command;
+
+const MICROARCHITECTURES_JSON: &str = "json/cpu/microarchitectures.json";
+const EXTRA_MICROARCHITECTURES_JSON: &str = "src/schema/extra_microarchitectures.json";
+const CPUID_JSON: &str = "json/cpu/cpuid.json";
+
+fn main() {
+    for path in [
+        MICROARCHITECTURES_JSON,
+        EXTRA_MICROARCHITECTURES_JSON,
+        CPUID_JSON,
+    ] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+
+    let mut out = String::from("// @generated by build.rs from the archspec-json files.\n\n");
+    write_microarchitectures(&mut out);
+    write_cpuid(&mut out);
+    write_version(&mut out);
+
+    let out_dir = std::env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo");
+    std::fs::write(Path::new(&out_dir).join("embedded.rs"), out)
+        .expect("failed to write the generated tables");
+}
+
+fn read_json(path: &str) -> Value {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("failed to read {}: {}", path, err));
+    serde_json::from_str(&contents)
+        .unwrap_or_else(|err| panic!("failed to parse {}: {}", path, err))
+}
+
+fn write_microarchitectures(out: &mut String) {
+    let schema = read_json(MICROARCHITECTURES_JSON);
+    let extra = read_json(EXTRA_MICROARCHITECTURES_JSON);
+
+    // Microarchitectures maintained in this crate are only added if the database lacks them.
+    let mut microarchitectures = object(&schema["microarchitectures"]).clone();
+    for (name, value) in object(&extra["microarchitectures"]) {
+        microarchitectures
+            .entry(name.clone())
+            .or_insert_with(|| value.clone());
+    }
+
+    out.push_str("static MICROARCHITECTURES: &[EmbeddedMicroarchitecture] = &[\n");
+    for (name, value) in &microarchitectures {
+        let compilers = match value.get("compilers") {
+            None | Some(Value::Null) => String::from("None"),
+            Some(compilers) => {
+                let sets = object(compilers)
+                    .iter()
+                    .map(|(compiler, set)| match set {
+                        Value::Array(entries) => format!(
+                            "({:?}, EmbeddedCompilerSet::Several(&[{}]))",
+                            compiler,
+                            entries
+                                .iter()
+                                .map(compiler_entry)
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                        entry => format!(
+                            "({:?}, EmbeddedCompilerSet::Single({}))",
+                            compiler,
+                            compiler_entry(entry)
+                        ),
+                    })
+                    .collect::<Vec<_>>();
+                format!("Some(&[{}])", sets.join(", "))
+            }
+        };
+        let generation = match value.get("generation") {
+            None | Some(Value::Null) => String::from("None"),
+            Some(generation) => format!("Some({})", generation.as_u64().expect("generation")),
+        };
+        writeln!(
+            out,
+            "    EmbeddedMicroarchitecture {{ name: {:?}, from: {}, vendor: {:?}, features: {}, \
+             compilers: {}, generation: {} }},",
+            name,
+            strings(&value["from"]),
+            string(&value["vendor"]),
+            strings(&value["features"]),
+            compilers,
+            generation,
+        )
+        .unwrap();
+    }
+    out.push_str("];\n\n");
+
+    out.push_str("static FEATURE_ALIASES: &[EmbeddedFeatureAlias] = &[\n");
+    for (name, value) in object(&schema["feature_aliases"]) {
+        writeln!(
+            out,
+            "    EmbeddedFeatureAlias {{ name: {:?}, reason: {}, any_of: {}, families: {} }},",
+            name,
+            optional(value.get("reason"), |reason| format!(
+                "{:?}",
+                string(reason)
+            )),
+            optional(value.get("any_of"), strings),
+            optional(value.get("families"), strings),
+        )
+        .unwrap();
+    }
+    out.push_str("];\n\n");
+
+    let conversions = &schema["conversions"];
+    writeln!(
+        out,
+        "static CONVERSIONS_DESCRIPTION: &str = {:?};\n",
+        string(&conversions["description"])
+    )
+    .unwrap();
+    for (table, key) in [
+        ("ARM_VENDORS", "arm_vendors"),
+        ("DARWIN_FLAGS", "darwin_flags"),
+    ] {
+        writeln!(out, "static {}: &[(&str, &str)] = &[", table).unwrap();
+        for (from, to) in object(&conversions[key]) {
+            writeln!(out, "    ({:?}, {:?}),", from, string(to)).unwrap();
+        }
+        out.push_str("];\n\n");
+    }
+}
+
+fn write_cpuid(out: &mut String) {
+    let schema = read_json(CPUID_JSON);
+
+    for (table, key) in [
+        ("CPUID_VENDOR", "vendor"),
+        (
+            "CPUID_HIGHEST_EXTENSION_SUPPORT",
+            "highest_extension_support",
+        ),
+    ] {
+        writeln!(
+            out,
+            "static {}: EmbeddedCpuIdProperty = {};\n",
+            table,
+            cpuid_property(&schema[key])
+        )
+        .unwrap();
+    }
+
+    for (table, key) in [
+        ("CPUID_FLAGS", "flags"),
+        ("CPUID_EXTENSION_FLAGS", "extension-flags"),
+    ] {
+        writeln!(out, "static {}: &[EmbeddedCpuIdFlags] = &[", table).unwrap();
+        for flags in array(&schema[key]) {
+            let bits = array(&flags["bits"])
+                .iter()
+                .map(|bits| {
+                    let register = match string(&bits["register"]) {
+                        "eax" => "Eax",
+                        "ebx" => "Ebx",
+                        "ecx" => "Ecx",
+                        "edx" => "Edx",
+                        register => panic!("unknown register {}", register),
+                    };
+                    format!(
+                        "({:?}, CpuRegister::{}, {})",
+                        string(&bits["name"]),
+                        register,
+                        bits["bit"].as_u64().expect("bit")
+                    )
+                })
+                .collect::<Vec<_>>();
+            writeln!(
+                out,
+                "    EmbeddedCpuIdFlags {{ property: {}, bits: &[{}] }},",
+                cpuid_property(flags),
+                bits.join(", ")
+            )
+            .unwrap();
+        }
+        out.push_str("];\n\n");
+    }
+}
+
+fn write_version(out: &mut String) {
+    // FNV-1a, the checksum only has to tell different versions of the files apart.
+    let mut checksum: u64 = 0xcbf29ce484222325;
+    for path in [
+        MICROARCHITECTURES_JSON,
+        EXTRA_MICROARCHITECTURES_JSON,
+        CPUID_JSON,
+    ] {
+        let contents =
+            std::fs::read(path).unwrap_or_else(|err| panic!("failed to read {}: {}", path, err));
+        for byte in contents {
+            checksum = (checksum ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    writeln!(
+        out,
+        "static JSON_COMMIT: Option<&str> = {};\n",
+        match json_commit() {
+            Some(commit) => format!("Some({:?})", commit),
+            None => String::from("None"),
+        }
+    )
+    .unwrap();
+    writeln!(out, "static JSON_CHECKSUM: &str = \"{:016x}\";", checksum).unwrap();
+}
+
+/// Returns the commit of the archspec-json checkout, which is not available when building from a
+/// packaged crate.
+fn json_commit() -> Option<String> {
+    // Without its own `.git` the directory would resolve to the repository of this crate.
+    if !Path::new("json/.git").exists() {
+        return None;
+    }
+
+    let git = |args: &[&str]| {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg("json")
+            .args(args)
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    // Checking out another commit of the submodule changes its HEAD.
+    if let Some(head) = git(&["rev-parse", "--git-path", "HEAD"]) {
+        println!(
+            "cargo:rerun-if-changed={}",
+            Path::new("json").join(head).display()
+        );
+    }
+    git(&["rev-parse", "HEAD"])
+}
+
+fn cpuid_property(value: &Value) -> String {
+    format!(
+        "EmbeddedCpuIdProperty {{ description: {:?}, eax: {}, ecx: {} }}",
+        string(&value["description"]),
+        value["input"]["eax"].as_u64().expect("eax"),
+        value["input"]["ecx"].as_u64().expect("ecx"),
+    )
+}
+
+fn compiler_entry(value: &Value) -> String {
+    format!(
+        "EmbeddedCompiler {{ versions: {:?}, flags: {:?}, name: {} }}",
+        string(&value["versions"]),
+        string(&value["flags"]),
+        optional(value.get("name"), |name| format!("{:?}", string(name))),
+    )
+}
+
+/// Formats {null, string, [string]} as a slice of strings.
+fn strings(value: &Value) -> String {
+    let strings = match value {
+        Value::Null => vec![],
+        Value::String(s) => vec![format!("{:?}", s)],
+        Value::Array(values) => values
+            .iter()
+            .map(|value| format!("{:?}", string(value)))
+            .collect(),
+        value => panic!("expected strings, found {}", value),
+    };
+    format!("&[{}]", strings.join(", "))
+}
+
+fn optional(value: Option<&Value>, format: impl FnOnce(&Value) -> String) -> String {
+    match value {
+        None | Some(Value::Null) => String::from("None"),
+        Some(value) => format!("Some({})", format(value)),
+    }
+}
+
+fn string(value: &Value) -> &str {
+    value
+        .as_str()
+        .unwrap_or_else(|| panic!("expected a string, found {}", value))
+}
+
+fn array(value: &Value) -> &Vec<Value> {
+    value
+        .as_array()
+        .unwrap_or_else(|| panic!("expected an array, found {}", value))
+}
+
+fn object(value: &Value) -> &Map<String, Value> {
+    value
+        .as_object()
+        .unwrap_or_else(|| panic!("expected an object, found {}", value))
+}
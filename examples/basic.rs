@@ -1,4 +1,4 @@
-fn main() -> Result<(), archspec::cpu::UnsupportedMicroarchitecture> {
+fn main() -> Result<(), archspec::cpu::DetectError> {
     let architecture = archspec::cpu::host()?;
 
     println!("Current CPU architecture:");
@@ -0,0 +1,446 @@
+//! A database of micro architectures, constructed from the archspec-json schemas.
+
+use super::detect::target_architecture_uname;
+use super::{DetectError, ExtendTargetsError, HostInfo, Microarchitecture};
+use crate::schema::{
+    find_cycle, read_file, Compiler, CompilerSet, CpuIdSchema, LoadError, MicroarchitecturesSchema,
+};
+use itertools::Itertools;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// The micro architectures of a schema together with the information that is needed to detect
+/// them.
+///
+/// Most functions of this crate use the default database returned by [`TargetDatabase::global`],
+/// but independent databases can be constructed from other versions of the schemas, e.g. to
+/// compare how a host is classified by each of them.
+#[derive(Debug)]
+pub struct TargetDatabase {
+    schema: Arc<MicroarchitecturesSchema>,
+    cpuid_schema: Arc<CpuIdSchema>,
+    targets: HashMap<String, Arc<Microarchitecture>>,
+}
+
+impl TargetDatabase {
+    /// Returns the default database, see [`Self::try_global`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the schemas cannot be loaded.
+    pub fn global() -> &'static Arc<TargetDatabase> {
+        Self::try_global().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Returns the default database. It is constructed from the embedded schemas, or the ones
+    /// from [`crate::schema::cpu_dir`], and includes the targets added with
+    /// [`Microarchitecture::extend_known_targets`].
+    pub fn try_global() -> Result<&'static Arc<TargetDatabase>, LoadError> {
+        static DATABASE: OnceLock<Result<Arc<TargetDatabase>, LoadError>> = OnceLock::new();
+        DATABASE
+            .get_or_init(|| {
+                let base = Self::try_base()?;
+                let mut database = TargetDatabase {
+                    schema: base.schema.clone(),
+                    cpuid_schema: base.cpuid_schema.clone(),
+                    targets: base.targets.clone(),
+                };
+                database
+                    .extend(Microarchitecture::take_custom_targets())
+                    .expect("custom targets are validated against the base database");
+                Ok(Arc::new(database))
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Returns the database constructed from the default schemas alone, which
+    /// [`Self::try_global`] extends with the custom targets. Custom targets are validated against
+    /// it, so that extending the default database cannot fail.
+    ///
+    /// The embedded schemas are generated at build time, but their targets are linked here on
+    /// first use.
+    pub(crate) fn try_base() -> Result<&'static TargetDatabase, LoadError> {
+        static DATABASE: OnceLock<Result<TargetDatabase, LoadError>> = OnceLock::new();
+        DATABASE
+            .get_or_init(|| {
+                Ok(TargetDatabase::new(
+                    MicroarchitecturesSchema::try_shared()?.clone(),
+                    CpuIdSchema::try_shared()?.clone(),
+                ))
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Constructs a database from the given schemas.
+    ///
+    /// Micro architectures that derive from an undefined micro architecture or from each other are
+    /// left out, [`MicroarchitecturesSchema::validate`] reports them.
+    pub fn new(
+        schema: impl Into<Arc<MicroarchitecturesSchema>>,
+        cpuid_schema: impl Into<Arc<CpuIdSchema>>,
+    ) -> Self {
+        let schema = schema.into();
+        let mut targets = HashMap::new();
+        for name in schema.microarchitectures.keys() {
+            if !targets.contains_key(name) {
+                fill_target_from_map(
+                    name,
+                    &schema.microarchitectures,
+                    &mut targets,
+                    &mut HashSet::new(),
+                );
+            }
+        }
+
+        if let Ok(host_platform) = target_architecture_uname() {
+            targets
+                .entry(host_platform.to_string())
+                .or_insert_with(|| Arc::new(Microarchitecture::generic(&host_platform)));
+        }
+
+        Self {
+            schema,
+            cpuid_schema: cpuid_schema.into(),
+            targets,
+        }
+    }
+
+    /// Constructs a database from the `microarchitectures.json` and `cpuid.json` files in the
+    /// given directory, e.g. the `cpu` directory of an archspec-json checkout. Unlike the default
+    /// database it only contains the micro architectures of these files, see
+    /// [`MicroarchitecturesSchema::with_extra_microarchitectures`].
+    pub fn from_dir(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let file = path.join("microarchitectures.json");
+        let schema = MicroarchitecturesSchema::parse(&file, &read_file(&file)?)?;
+        let file = path.join("cpuid.json");
+        let cpuid_schema = CpuIdSchema::parse(&file, &read_file(&file)?)?;
+        Ok(Self::new(schema, cpuid_schema))
+    }
+
+    /// Returns the schema the micro architectures were constructed from. Targets that were added
+    /// with [`Self::extend`] are not part of it.
+    pub fn schema(&self) -> &MicroarchitecturesSchema {
+        &self.schema
+    }
+
+    /// Returns the schema that describes how to read features with `CPUID`.
+    pub fn cpuid_schema(&self) -> &CpuIdSchema {
+        &self.cpuid_schema
+    }
+
+    /// Returns all the micro architectures in this database.
+    pub fn targets(&self) -> &HashMap<String, Arc<Microarchitecture>> {
+        &self.targets
+    }
+
+    /// Returns the micro architecture with the given name.
+    pub fn get(&self, name: &str) -> Option<&Arc<Microarchitecture>> {
+        self.targets.get(name)
+    }
+
+    /// Adds custom micro architectures, e.g. site-local targets, to this database.
+    ///
+    /// The parents of every micro architecture must be part of the database or of `definitions`,
+    /// and they must not derive from each other.
+    pub fn extend(
+        &mut self,
+        definitions: HashMap<String, crate::schema::Microarchitecture>,
+    ) -> Result<(), ExtendTargetsError> {
+        validate_definitions(&definitions, |name| self.targets.contains_key(name))?;
+        for name in definitions.keys() {
+            if !self.targets.contains_key(name) {
+                fill_target_from_map(name, &definitions, &mut self.targets, &mut HashSet::new());
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares two micro architectures by name. A micro architecture is less than its
+    /// descendants, unrelated or unknown micro architectures cannot be compared.
+    pub fn compare(&self, a: &str, b: &str) -> Option<Ordering> {
+        let (a, b) = (self.get(a)?, self.get(b)?);
+        if a == b {
+            Some(Ordering::Equal)
+        } else if b.decendent_of(a) {
+            Some(Ordering::Less)
+        } else if a.decendent_of(b) {
+            Some(Ordering::Greater)
+        } else {
+            None
+        }
+    }
+
+    /// Detects the micro architecture of the host among the targets of this database.
+    ///
+    /// Unlike [`super::host`] the result is not cached and cannot be overridden.
+    pub fn host(self: &Arc<Self>) -> Result<Arc<Microarchitecture>, DetectError> {
+        self.host_info().map(|info| info.target().clone())
+    }
+
+    /// Detects the micro architecture of the host among the targets of this database, together
+    /// with the detected features.
+    pub fn host_info(self: &Arc<Self>) -> Result<HostInfo, DetectError> {
+        super::TargetDetector::new()
+            .with_database(self.clone())
+            .detect_info()
+    }
+}
+
+/// Constructs the micro architecture with the given name from `data`, after its parents. Micro
+/// architectures that are already in `targets` are not constructed again.
+///
+/// Returns false if the micro architecture cannot be constructed because it is not defined, or it
+/// derives from a micro architecture that is not defined or from itself.
+fn fill_target_from_map<'a>(
+    name: &'a str,
+    data: &'a HashMap<String, crate::schema::Microarchitecture>,
+    targets: &mut HashMap<String, Arc<Microarchitecture>>,
+    visiting: &mut HashSet<&'a str>,
+) -> bool {
+    let Some((name, values)) = data.get_key_value(name) else {
+        return false;
+    };
+    if !visiting.insert(name) {
+        return false;
+    }
+    let parent_names = &values.from;
+    let mut parents_constructed = true;
+    for parent in parent_names {
+        if !targets.contains_key(parent) {
+            parents_constructed &= fill_target_from_map(parent, data, targets, visiting);
+        }
+    }
+    visiting.remove(name.as_str());
+    if !parents_constructed {
+        return false;
+    }
+    let parents = parent_names
+        .iter()
+        .map(|parent| targets[parent].clone())
+        .collect::<Vec<Arc<Microarchitecture>>>();
+
+    let vendor = values.vendor.clone();
+    let features: HashSet<String> = values.features.iter().cloned().collect();
+    let compilers: HashMap<String, Vec<Compiler>> = values
+        .compilers
+        .as_ref()
+        .map(|compilers| {
+            compilers
+                .iter()
+                .map(|(vendor, set)| {
+                    (
+                        vendor.clone(),
+                        // normalize to a sequence of compiler definitions
+                        match set {
+                            CompilerSet::Several(cs) => cs.clone(),
+                            CompilerSet::Single(c) => vec![c.clone()],
+                        },
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let generation = values.generation.unwrap_or(0);
+
+    targets.insert(
+        name.to_string(),
+        Arc::new(Microarchitecture::new_generation(
+            name.to_string(),
+            parents,
+            vendor,
+            features,
+            compilers,
+            generation,
+        )),
+    );
+    true
+}
+
+/// Checks that custom micro architectures are not known yet, that their parents are known or
+/// part of `definitions`, and that they do not derive from each other.
+pub(crate) fn validate_definitions(
+    definitions: &HashMap<String, crate::schema::Microarchitecture>,
+    is_known: impl Fn(&str) -> bool,
+) -> Result<(), ExtendTargetsError> {
+    for (name, definition) in definitions.iter().sorted_by_key(|(name, _)| *name) {
+        if is_known(name) {
+            return Err(ExtendTargetsError::AlreadyKnown(name.clone()));
+        }
+        if let Some(parent) = definition
+            .from
+            .iter()
+            .find(|parent| !is_known(parent) && !definitions.contains_key(*parent))
+        {
+            return Err(ExtendTargetsError::UnknownParent {
+                target: name.clone(),
+                parent: parent.clone(),
+            });
+        }
+    }
+
+    match find_cycle(definitions) {
+        Some(cycle) => Err(ExtendTargetsError::Cycle(cycle)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TargetDatabase;
+    use crate::cpu::ExtendTargetsError;
+    use crate::schema::{CpuIdSchema, LoadError, MicroarchitecturesSchema};
+    use std::cmp::Ordering;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_from_dir() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/json/cpu");
+        let database = TargetDatabase::from_dir(dir).unwrap();
+        assert!(database.get("rva23u64").is_none());
+        assert!(TargetDatabase::global().get("rva23u64").is_some());
+
+        // With the micro architectures this crate maintains, it matches the default database.
+        let schema = MicroarchitecturesSchema::from_json(
+            &std::fs::read_to_string(Path::new(dir).join("microarchitectures.json")).unwrap(),
+        )
+        .and_then(MicroarchitecturesSchema::with_extra_microarchitectures)
+        .unwrap();
+        let database = Arc::new(TargetDatabase::new(
+            schema,
+            CpuIdSchema::try_shared().unwrap().clone(),
+        ));
+        assert_eq!(
+            database.targets().len(),
+            TargetDatabase::global().targets().len()
+        );
+        assert_eq!(
+            database.host().map(|target| target.name().to_string()),
+            TargetDatabase::global()
+                .host()
+                .map(|target| target.name().to_string())
+        );
+    }
+
+    #[test]
+    fn test_from_missing_dir() {
+        let err = TargetDatabase::from_dir("/path/to/nowhere").unwrap_err();
+        assert!(matches!(
+            err,
+            LoadError::Io { file, .. } if file.ends_with("microarchitectures.json")
+        ));
+    }
+
+    #[test]
+    fn test_invalid_parents() {
+        let schema = MicroarchitecturesSchema::from_json(
+            r#"{
+                "microarchitectures": {
+                    "x86_64": { "from": [], "vendor": "generic", "features": [] },
+                    "loop_a": { "from": ["x86_64", "loop_b"], "vendor": "generic", "features": [] },
+                    "loop_b": { "from": "loop_a", "vendor": "generic", "features": [] },
+                    "orphan": { "from": "unknown", "vendor": "generic", "features": [] }
+                },
+                "feature_aliases": {},
+                "conversions": { "description": "", "arm_vendors": {}, "darwin_flags": {} }
+            }"#,
+        )
+        .unwrap();
+        let mut database = TargetDatabase::new(schema, CpuIdSchema::try_shared().unwrap().clone());
+        assert!(database.get("x86_64").is_some());
+        assert!(database.get("loop_a").is_none());
+        assert!(database.get("loop_b").is_none());
+        assert!(database.get("orphan").is_none());
+
+        // Targets that were left out cannot be extended.
+        assert_eq!(
+            database.extend(
+                serde_json::from_str(
+                    r#"{ "child": { "from": "orphan", "vendor": "generic", "features": [] } }"#
+                )
+                .unwrap()
+            ),
+            Err(ExtendTargetsError::UnknownParent {
+                target: "child".to_string(),
+                parent: "orphan".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_extend_validation() {
+        let mut database = TargetDatabase::new(
+            MicroarchitecturesSchema::try_shared().unwrap().clone(),
+            CpuIdSchema::try_shared().unwrap().clone(),
+        );
+        let mut extend = |json: &str| database.extend(serde_json::from_str(json).unwrap());
+
+        assert_eq!(
+            extend(
+                r#"{ "icelake": { "from": "x86_64_v3", "vendor": "GenuineIntel", "features": [] } }"#
+            ),
+            Err(ExtendTargetsError::AlreadyKnown("icelake".to_string()))
+        );
+        assert_eq!(
+            extend(
+                r#"{ "ourcloud_v1": { "from": "ourcloud_v0", "vendor": "generic", "features": [] } }"#
+            ),
+            Err(ExtendTargetsError::UnknownParent {
+                target: "ourcloud_v1".to_string(),
+                parent: "ourcloud_v0".to_string()
+            })
+        );
+        assert_eq!(
+            extend(
+                r#"{
+                    "ourcloud_v1": { "from": ["x86_64_v3", "ourcloud_v2"], "vendor": "generic", "features": [] },
+                    "ourcloud_v2": { "from": "ourcloud_v1", "vendor": "generic", "features": [] }
+                }"#
+            ),
+            Err(ExtendTargetsError::Cycle(vec![
+                "ourcloud_v1".to_string(),
+                "ourcloud_v2".to_string(),
+                "ourcloud_v1".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_extend_and_compare() {
+        let global = TargetDatabase::global();
+        let mut database = TargetDatabase::new(
+            MicroarchitecturesSchema::try_shared().unwrap().clone(),
+            CpuIdSchema::try_shared().unwrap().clone(),
+        );
+        database
+            .extend(
+                serde_json::from_str(
+                    r#"{ "ourcloud_v1": { "from": "x86_64_v3", "vendor": "generic", "features": ["avx512f"] } }"#,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+        assert!(database.get("ourcloud_v1").is_some());
+        assert!(global.get("ourcloud_v1").is_none());
+        assert_eq!(
+            database.compare("x86_64_v3", "ourcloud_v1"),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            database.compare("ourcloud_v1", "x86_64"),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            database.compare("ourcloud_v1", "ourcloud_v1"),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(database.compare("ourcloud_v1", "icelake"), None);
+        assert_eq!(database.compare("ourcloud_v1", "unknown"), None);
+    }
+}
@@ -1,17 +1,24 @@
 #![allow(dead_code)]
 
-use super::microarchitecture::{Microarchitecture, UnsupportedMicroarchitecture};
+use super::database::TargetDatabase;
+use super::hwcap::{
+    HwCapProvider, MachineHwCapProvider, AARCH64_HWCAP, AARCH64_HWCAP2, AARCH64_HWCAP_CPUID,
+    PPC64_HWCAP, PPC64_HWCAP2,
+};
+use super::microarchitecture::{DetectError, Microarchitecture, UnsupportedMicroarchitecture};
 use crate::cpuid::{CpuId, CpuIdProvider, MachineCpuIdProvider};
+use crate::schema::{CpuIdSchema, LoadError, MicroarchitecturesSchema};
 use itertools::Itertools;
 use std::{
+    borrow::Cow,
     cmp::Ordering,
     collections::{HashMap, HashSet},
     io::{BufRead, BufReader, Cursor},
-    sync::Arc,
+    sync::{Arc, OnceLock},
 };
 
 /// Returns the architecture as defined by the compiler.
-const fn target_architecture_compiler() -> &'static str {
+pub(crate) const fn target_architecture_compiler() -> &'static str {
     // HACK: Cannot compare strings in const context, but we can compare bytes.
     match std::env::consts::ARCH.as_bytes() {
         b"powerpc64" if cfg!(target_endian = "little") => "ppc64le",
@@ -20,6 +27,16 @@ const fn target_architecture_compiler() -> &'static str {
     }
 }
 
+/// Maps the different names of an architecture to the name of its family, e.g. `i686` to `x86`.
+pub(super) fn normalize_architecture(arch: &str) -> &str {
+    match arch {
+        "i386" | "i486" | "i586" | "i686" => "x86",
+        "amd64" => "x86_64",
+        "arm64" => "aarch64",
+        _ => arch,
+    }
+}
+
 /// Returns the architecture of the host machine by querying uname.
 #[cfg(not(target_os = "windows"))]
 pub(crate) fn target_architecture_uname() -> std::io::Result<String> {
@@ -43,15 +60,19 @@ pub(crate) fn target_architecture_uname() -> std::io::Result<String> {
     Ok(target_architecture_compiler().to_string())
 }
 
-pub(crate) struct ProcCpuInfo {
+/// The key-value pairs describing the first processor in `/proc/cpuinfo` on Linux.
+#[derive(Default, Clone)]
+pub struct ProcCpuInfo {
     cpu_info: HashMap<String, String>,
 }
 
 impl ProcCpuInfo {
-    pub fn from_str(contents: &str) -> Self {
+    /// Parses the contents of `/proc/cpuinfo`.
+    pub fn parse(contents: &str) -> Self {
         Self::from_reader(Cursor::new(contents.as_bytes()))
     }
 
+    /// Parses the contents of `/proc/cpuinfo` from a reader.
     pub fn from_reader(reader: impl BufRead) -> Self {
         let mut cpu_info = std::collections::HashMap::new();
         for line in reader.lines() {
@@ -77,19 +98,116 @@ impl ProcCpuInfo {
         Ok(Self::from_reader(BufReader::new(file)))
     }
 
+    /// Returns the value of the given key, e.g. `vendor_id`.
     pub fn get(&self, key: &str) -> Option<&str> {
         self.cpu_info.get(key).map(String::as_str)
     }
+
+    /// Returns the fields of the Main ID Register of an AArch64 CPU, if present.
+    pub(crate) fn arm_cpu_id(&self) -> Option<ArmCpuId> {
+        fn parse(value: &str) -> Option<u32> {
+            match value.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => value.parse().ok(),
+            }
+        }
+
+        Some(ArmCpuId {
+            implementer: parse(self.get("CPU implementer")?)?,
+            variant: self.get("CPU variant").and_then(parse).unwrap_or(0),
+            architecture: self.get("CPU architecture").and_then(parse).unwrap_or(0),
+            part: parse(self.get("CPU part")?)?,
+        })
+    }
+}
+
+impl ProcCpuInfo {
+    /// Returns the machine type of an IBM Z processor, e.g. `3906` for a z14.
+    ///
+    /// The machine type is part of the lines that describe each processor, e.g.
+    /// `processor 0: version = FF,  identification = 0133E8,  machine = 3906`.
+    pub(crate) fn s390x_machine_type(&self) -> Option<u32> {
+        self.cpu_info
+            .iter()
+            .filter(|(key, _)| key.starts_with("processor"))
+            .flat_map(|(_, value)| value.split(','))
+            .find_map(|field| field.trim().strip_prefix("machine = "))
+            .and_then(|machine| machine.trim().parse().ok())
+    }
+}
+
+/// The fields of the AArch64 Main ID Register (MIDR_EL1) as reported by the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ArmCpuId {
+    /// The implementer code, e.g. `0x41` for ARM.
+    pub implementer: u32,
+
+    /// The major revision of the part.
+    pub variant: u32,
+
+    /// The architecture version, `8` for all ARMv8 and ARMv9 CPUs.
+    pub architecture: u32,
+
+    /// The implementer specific part number.
+    pub part: u32,
+}
+
+impl ArmCpuId {
+    /// Decodes the fields of the `MIDR_EL1` register.
+    pub fn from_midr(midr: u64) -> Self {
+        Self {
+            implementer: ((midr >> 24) & 0xff) as u32,
+            variant: ((midr >> 20) & 0xf) as u32,
+            architecture: ((midr >> 16) & 0xf) as u32,
+            part: ((midr >> 4) & 0xfff) as u32,
+        }
+    }
+
+    /// Returns the name of the microarchitecture that corresponds with this part, if known.
+    pub fn model(&self) -> Option<&'static str> {
+        AARCH64_PARTS
+            .iter()
+            .find(|(implementer, part, _)| *implementer == self.implementer && *part == self.part)
+            .map(|(_, _, name)| *name)
+    }
 }
 
+/// Maps implementer and part numbers to the microarchitecture they identify. This list is taken
+/// from:
+///
+/// https://github.com/gcc-mirror/gcc/blob/master/gcc/config/aarch64/aarch64-cores.def
+/// https://github.com/AsahiLinux/docs/wiki/HW:ARM-System-Registers
+const AARCH64_PARTS: &[(u32, u32, &str)] = &[
+    (0x41, 0xd08, "cortex_a72"),
+    (0x41, 0xd0c, "neoverse_n1"),
+    (0x41, 0xd40, "neoverse_v1"),
+    (0x41, 0xd49, "neoverse_n2"),
+    (0x41, 0xd4f, "neoverse_v2"),
+    (0x43, 0x0af, "thunderx2"),
+    (0x46, 0x001, "a64fx"),
+    (0x61, 0x022, "m1"),
+    (0x61, 0x023, "m1"),
+    (0x61, 0x024, "m1"),
+    (0x61, 0x025, "m1"),
+    (0x61, 0x028, "m1"),
+    (0x61, 0x029, "m1"),
+    (0x61, 0x032, "m2"),
+    (0x61, 0x033, "m2"),
+    (0x61, 0x034, "m2"),
+    (0x61, 0x035, "m2"),
+    (0x61, 0x038, "m2"),
+    (0x61, 0x039, "m2"),
+];
+
 /// Returns the micro architecture of a Windows machine with the specified properties.
 pub(crate) fn detect_windows<C: CpuIdProvider>(
     arch: &str,
     cpuid: &C,
+    cpuid_schema: &CpuIdSchema,
 ) -> Result<Microarchitecture, UnsupportedMicroarchitecture> {
     match arch {
         "x86_64" | "x86" => {
-            let cpuid = CpuId::detect(cpuid);
+            let cpuid = CpuId::detect(cpuid, cpuid_schema);
             Ok(Microarchitecture {
                 name: String::new(),
                 parents: vec![],
@@ -107,9 +225,14 @@ pub(crate) fn detect_windows<C: CpuIdProvider>(
     }
 }
 
-fn detect_linux(arch: &str, cpu_info: &ProcCpuInfo) -> Microarchitecture {
+fn detect_linux<H: HwCapProvider>(
+    arch: &str,
+    cpu_info: &ProcCpuInfo,
+    hwcap: &H,
+    schema: &MicroarchitecturesSchema,
+) -> Microarchitecture {
     match arch {
-        "x86_64" => Microarchitecture {
+        "x86_64" | "x86" => Microarchitecture {
             vendor: cpu_info.get("vendor_id").unwrap_or("generic").to_string(),
             features: cpu_info
                 .get("flags")
@@ -120,47 +243,68 @@ fn detect_linux(arch: &str, cpu_info: &ProcCpuInfo) -> Microarchitecture {
             ..Microarchitecture::generic("")
         },
         "aarch64" => {
-            let vendor = if let Some(implementer) = cpu_info.get("CPU implementer") {
-                // Mapping numeric codes to vendor (ARM). This list is a merge from
-                // different sources:
-                //
-                // https://github.com/karelzak/util-linux/blob/master/sys-utils/lscpu-arm.c
-                // https://developer.arm.com/docs/ddi0487/latest/arm-architecture-reference-manual-armv8-for-armv8-a-architecture-profile
-                // https://github.com/gcc-mirror/gcc/blob/master/gcc/config/aarch64/aarch64-cores.def
-                // https://patchwork.kernel.org/patch/10524949/
-                crate::schema::MicroarchitecturesSchema::schema()
-                    .conversions
-                    .arm_vendors
-                    .get(implementer)
-                    .cloned()
-                    .unwrap_or_else(|| "generic".to_string())
-            } else {
-                String::from("generic")
+            // Prefer /proc/cpuinfo but fall back to the auxiliary vector if it is not available,
+            // e.g. in a sandbox.
+            let hwcaps = hwcap.hwcaps(arch).ok();
+            let cpu_id = cpu_info.arm_cpu_id().or_else(|| {
+                hwcaps
+                    .filter(|hwcaps| hwcaps.hwcap & AARCH64_HWCAP_CPUID != 0)
+                    .and_then(|_| hwcap.midr_el1().ok())
+                    .map(ArmCpuId::from_midr)
+            });
+
+            let vendor = match (cpu_info.get("CPU implementer"), cpu_id) {
+                (Some(implementer), _) => arm_vendor(schema, implementer),
+                (None, Some(cpu_id)) => arm_vendor(schema, &format!("{:#04x}", cpu_id.implementer)),
+                (None, None) => String::from("generic"),
             };
 
-            Microarchitecture {
-                vendor,
-                features: cpu_info
-                    .get("Features")
-                    .unwrap_or_default()
+            let features = match cpu_info.get("Features") {
+                Some(features) => features
                     .split_ascii_whitespace()
                     .map(|s| s.to_string())
                     .collect(),
-                ..Microarchitecture::generic("")
+                None => hwcaps
+                    .map(|hwcaps| hwcaps.features(AARCH64_HWCAP, AARCH64_HWCAP2))
+                    .unwrap_or_default(),
+            };
+
+            // If the part number is known, the exact model can be determined. Otherwise the model
+            // is inferred from the features.
+            let model = cpu_id.and_then(|cpu_id| cpu_id.model()).unwrap_or_default();
+
+            Microarchitecture {
+                vendor,
+                features,
+                ..Microarchitecture::generic(model)
             }
         }
         "ppc64" | "ppc64le" => {
-            let cpu = cpu_info.get("cpu").unwrap_or_default();
-            let generation = cpu
-                .strip_prefix("POWER")
-                .map(|rest| {
-                    rest.split_once(|c: char| !c.is_ascii_digit())
-                        .map_or(rest, |(digits, _)| digits)
-                })
-                .and_then(|gen| gen.parse().ok())
-                .unwrap_or(0);
+            let cpu = PowerCpu::parse(cpu_info.get("cpu").unwrap_or_default());
+
+            let mut features = hwcap
+                .hwcaps(arch)
+                .map(|hwcaps| hwcaps.features(PPC64_HWCAP, PPC64_HWCAP2))
+                .unwrap_or_default();
+            if cpu.altivec {
+                features.insert(String::from("altivec"));
+            }
+
+            // The ISA level reported by the kernel reflects the compatibility mode the processor
+            // runs in, e.g. a POWER10 in a partition that is restricted to POWER9. Binaries must
+            // not use anything beyond that level. In architected mode the name already is the
+            // generation presented to the operating system, while a kernel that predates it may
+            // not report its ISA level.
+            let generation = match (cpu.generation, power_isa_generation(&features)) {
+                (generation, _) if cpu.architected && generation != 0 => generation,
+                (0, Some(isa_generation)) => isa_generation,
+                (generation, Some(isa_generation)) => generation.min(isa_generation),
+                (generation, None) => generation,
+            };
+
             Microarchitecture {
                 generation,
+                features,
                 ..Microarchitecture::generic("")
             }
         }
@@ -170,18 +314,241 @@ fn detect_linux(arch: &str, cpu_info: &ProcCpuInfo) -> Microarchitecture {
                 Some(uarch) => uarch,
                 None => "riscv64",
             };
-            Microarchitecture::generic(uarch)
+            Microarchitecture {
+                features: riscv_isa_features(cpu_info.get("isa").unwrap_or_default()),
+                ..Microarchitecture::generic(uarch)
+            }
+        }
+        "loongarch64" => {
+            let model_name = cpu_info.get("Model Name").unwrap_or_default();
+            let vendor = if model_name.starts_with("Loongson") {
+                "Loongson"
+            } else {
+                "generic"
+            };
+            let model = LOONGARCH64_MODELS
+                .iter()
+                .find(|(prefix, _)| model_name.starts_with(prefix))
+                .map_or("", |(_, model)| model);
+
+            Microarchitecture {
+                vendor: vendor.to_string(),
+                features: cpu_info
+                    .get("Features")
+                    .unwrap_or_default()
+                    .split_ascii_whitespace()
+                    .map(|s| s.to_string())
+                    .collect(),
+                ..Microarchitecture::generic(model)
+            }
+        }
+        "s390x" => {
+            let vendor = match cpu_info.get("vendor_id") {
+                Some(vendor) if vendor.starts_with("IBM") => "IBM",
+                _ => "generic",
+            };
+            let generation = cpu_info
+                .s390x_machine_type()
+                .and_then(|machine| {
+                    S390X_MACHINE_TYPES
+                        .iter()
+                        .find(|(machine_type, _)| *machine_type == machine)
+                })
+                .map_or(0, |(_, generation)| *generation);
+
+            Microarchitecture {
+                vendor: vendor.to_string(),
+                features: cpu_info
+                    .get("features")
+                    .unwrap_or_default()
+                    .split_ascii_whitespace()
+                    .map(|s| s.to_string())
+                    .collect(),
+                generation,
+                ..Microarchitecture::generic("")
+            }
         }
         _ => Microarchitecture::generic(arch),
     }
 }
 
-pub(crate) trait SysCtlProvider {
+/// Maps the prefix of the `Model Name` of Loongson processors to their core.
+const LOONGARCH64_MODELS: &[(&str, &str)] = &[
+    ("Loongson-3A5", "la464"),
+    ("Loongson-3B5", "la464"),
+    ("Loongson-3C5", "la464"),
+    ("Loongson-3D5", "la464"),
+    ("Loongson-3A6", "la664"),
+    ("Loongson-3B6", "la664"),
+    ("Loongson-3C6", "la664"),
+    ("Loongson-3D6", "la664"),
+];
+
+/// Maps the machine types of IBM Z to the generation of the processor.
+const S390X_MACHINE_TYPES: &[(u32, usize)] = &[
+    (2964, 13),
+    (2965, 13),
+    (3906, 14),
+    (3907, 14),
+    (8561, 15),
+    (8562, 15),
+    (3931, 16),
+    (3932, 16),
+    (9175, 17),
+    (9176, 17),
+];
+
+/// The processor described by the `cpu` line of `/proc/cpuinfo` on POWER, e.g.
+/// `POWER9 (architected), altivec supported`.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct PowerCpu {
+    /// The generation of the processor, or 0 if unknown.
+    generation: usize,
+
+    /// Whether the processor runs in an architected (compatibility) mode instead of its raw mode.
+    /// In this mode the name reflects the generation that is presented to the operating system.
+    architected: bool,
+
+    /// Whether the kernel reported AltiVec support.
+    altivec: bool,
+}
+
+impl PowerCpu {
+    fn parse(cpu: &str) -> Self {
+        let (name, attributes) = cpu.split_once(',').unwrap_or((cpu, ""));
+        let (model, mode) = match name.split_once('(') {
+            Some((model, mode)) => (model.trim(), mode.trim_end_matches(')').trim()),
+            None => (name.trim(), ""),
+        };
+
+        let generation = model
+            .strip_prefix("POWER")
+            .map(|rest| {
+                rest.split_once(|c: char| !c.is_ascii_digit())
+                    .map_or(rest, |(digits, _)| digits)
+            })
+            .and_then(|gen| gen.parse().ok())
+            .unwrap_or(0);
+
+        Self {
+            generation,
+            architected: mode == "architected",
+            altivec: attributes.contains("altivec supported"),
+        }
+    }
+}
+
+/// Returns the generation of POWER processor that introduced the highest ISA level in `features`.
+fn power_isa_generation(features: &HashSet<String>) -> Option<usize> {
+    [
+        ("arch_3_1", 10),
+        ("arch_3_00", 9),
+        ("arch_2_07", 8),
+        ("arch_2_06", 7),
+        ("arch_2_05", 6),
+    ]
+    .iter()
+    .find(|(isa, _)| features.contains(*isa))
+    .map(|(_, generation)| *generation)
+}
+
+/// Parses a RISC-V ISA string like `rv64imafdch_zicsr_zifencei_zba_zbb` into the extensions it
+/// contains. Single-letter extensions are returned as-is, `g` is expanded to the extensions it is
+/// an abbreviation of, and `i` implies `zicsr` and `zifencei`, which were part of it before they
+/// became separate extensions. Multi-letter extensions (`Z*`, `S*` and `X*`) are returned without
+/// their version suffix.
+fn riscv_isa_features(isa: &str) -> HashSet<String> {
+    let isa = isa.trim().to_ascii_lowercase();
+    let Some(isa) = isa
+        .strip_prefix("rv64")
+        .or_else(|| isa.strip_prefix("rv32"))
+    else {
+        return HashSet::new();
+    };
+
+    /// Strips a version suffix like `2p0` from an extension name.
+    fn strip_version(extension: &str) -> &str {
+        let is_digit = |c: char| c.is_ascii_digit();
+        let name = extension.trim_end_matches(is_digit);
+        match name.strip_suffix('p') {
+            Some(major) if name.len() < extension.len() && major.ends_with(is_digit) => {
+                major.trim_end_matches(is_digit)
+            }
+            _ => name,
+        }
+    }
+
+    let mut features = HashSet::new();
+    let mut segments = isa.split('_');
+
+    // The first segment contains the single-letter extensions, optionally followed by the first
+    // multi-letter extension.
+    let base = segments.next().unwrap_or_default();
+    let (single, first_multi) = match base.find(['z', 's', 'x']) {
+        Some(index) => base.split_at(index),
+        None => (base, ""),
+    };
+    let mut chars = single.chars().peekable();
+    while let Some(c) = chars.next() {
+        // Skip version numbers like `2p1`.
+        if c.is_ascii_digit() || (c == 'p' && chars.peek().is_some_and(char::is_ascii_digit)) {
+            continue;
+        }
+        if c == 'i' {
+            // Like the Linux kernel, treat `i` as implying the extensions that were split off from
+            // it to support ISA strings of older kernels.
+            features.extend(["i", "zicsr", "zifencei"].iter().map(|s| s.to_string()));
+        } else if c == 'g' {
+            features.extend(
+                ["i", "m", "a", "f", "d", "zicsr", "zifencei"]
+                    .iter()
+                    .map(|s| s.to_string()),
+            );
+        } else {
+            features.insert(c.to_string());
+        }
+    }
+
+    for extension in std::iter::once(first_multi).chain(segments) {
+        let extension = strip_version(extension);
+        if !extension.is_empty() {
+            features.insert(extension.to_string());
+        }
+    }
+
+    features
+}
+
+/// Returns the vendor name for the implementer code of an ARM CPU.
+fn arm_vendor(schema: &MicroarchitecturesSchema, implementer: &str) -> String {
+    // Mapping numeric codes to vendor (ARM). This list is a merge from
+    // different sources:
+    //
+    // https://github.com/karelzak/util-linux/blob/master/sys-utils/lscpu-arm.c
+    // https://developer.arm.com/docs/ddi0487/latest/arm-architecture-reference-manual-armv8-for-armv8-a-architecture-profile
+    // https://github.com/gcc-mirror/gcc/blob/master/gcc/config/aarch64/aarch64-cores.def
+    // https://patchwork.kernel.org/patch/10524949/
+    schema
+        .conversions
+        .arm_vendors
+        .get(implementer)
+        .cloned()
+        .unwrap_or_else(|| "generic".to_string())
+}
+
+/// Provides the values of `sysctl` entries on macOS.
+pub trait SysCtlProvider {
+    /// Returns the value of the `sysctl` entry with the given name.
     fn sysctl(&self, name: &str) -> std::io::Result<String>;
 }
 
+/// Default implementation of the [`SysCtlProvider`] trait, which reads the entries of the
+/// machine this is running on.
+///
+/// `sysctl` is only available on macOS, on other operating systems every entry fails to read
+/// with [`std::io::ErrorKind::Unsupported`].
 #[derive(Default)]
-pub(crate) struct MachineSysCtlProvider {}
+pub struct MachineSysCtlProvider {}
 
 impl SysCtlProvider for MachineSysCtlProvider {
     fn sysctl(&self, name: &str) -> std::io::Result<String> {
@@ -193,13 +560,18 @@ impl SysCtlProvider for MachineSysCtlProvider {
                     .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
                     .map(|v| v.to_string())
             } else {
-                unimplemented!("Sysctl is not implemented for this platform, requesting {name}")
+                let _ = name;
+                Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
             }
         }
     }
 }
 
-fn detect_macos<S: SysCtlProvider>(arch: &str, sysctl: &S) -> Microarchitecture {
+fn detect_macos<S: SysCtlProvider>(
+    arch: &str,
+    sysctl: &S,
+    database: &TargetDatabase,
+) -> Microarchitecture {
     match arch {
         "x86_64" => {
             let cpu_features = sysctl
@@ -219,11 +591,7 @@ fn detect_macos<S: SysCtlProvider>(arch: &str, sysctl: &S) -> Microarchitecture
                 .collect::<HashSet<String>>();
 
             // Flags detected on Darwin turned to their linux counterpart.
-            for (darwin_flag, linux_flag) in crate::schema::MicroarchitecturesSchema::schema()
-                .conversions
-                .darwin_flags
-                .iter()
-            {
+            for (darwin_flag, linux_flag) in database.schema().conversions.darwin_flags.iter() {
                 if features.contains(darwin_flag) {
                     features.extend(linux_flag.split_whitespace().map(|s| s.to_string()))
                 }
@@ -236,25 +604,218 @@ fn detect_macos<S: SysCtlProvider>(arch: &str, sysctl: &S) -> Microarchitecture
             }
         }
         _ => {
-            let model = match sysctl
-                .sysctl("machdep.cpu.brand_string")
-                .map(|v| v.to_string().to_lowercase())
-                .ok()
-            {
-                Some(model) if model.contains("m2") => String::from("m2"),
-                Some(model) if model.contains("m1") => String::from("m1"),
-                Some(model) if model.contains("apple") => String::from("m1"),
-                _ => String::from("unknown"),
-            };
+            let model = detect_apple_model(sysctl, database.targets());
+
+            // Flags detected on Darwin turned to their linux counterpart. The database takes
+            // precedence over the built-in table.
+            let darwin_flags = &database.schema().conversions.darwin_flags;
+            let mut features = HashSet::new();
+            for (darwin_flag, linux_flags) in DARWIN_ARM_FLAGS {
+                if sysctl_enabled(sysctl, &format!("hw.optional.{darwin_flag}")) {
+                    let linux_flags = darwin_flags
+                        .get(*darwin_flag)
+                        .map_or(*linux_flags, String::as_str);
+                    features.extend(linux_flags.split_whitespace().map(|s| s.to_string()));
+                }
+            }
 
             Microarchitecture {
                 vendor: String::from("Apple"),
-                ..Microarchitecture::generic(&model)
+                features,
+                ..Microarchitecture::generic(model)
             }
         }
     }
 }
 
+/// Returns true if the boolean sysctl with the given name is set.
+pub(super) fn sysctl_enabled<S: SysCtlProvider>(sysctl: &S, name: &str) -> bool {
+    sysctl.sysctl(name).is_ok_and(|value| value.trim() == "1")
+}
+
+/// Maps the `hw.optional` sysctls of arm64 macOS to their linux counterpart. This complements
+/// `conversions.darwin_flags` of the database which only covers x86_64.
+const DARWIN_ARM_FLAGS: &[(&str, &str)] = &[
+    ("floatingpoint", "fp"),
+    ("AdvSIMD", "asimd"),
+    ("AdvSIMD_HPFPCvt", "fphp asimdhp"),
+    ("armv8_crc32", "crc32"),
+    ("armv8_1_atomics", "atomics"),
+    ("armv8_2_fhm", "asimdfhm"),
+    ("armv8_2_sha512", "sha512"),
+    ("armv8_2_sha3", "sha3"),
+    ("armv8_3_compnum", "fcma"),
+    ("arm.FEAT_AES", "aes"),
+    ("arm.FEAT_PMULL", "pmull"),
+    ("arm.FEAT_SHA1", "sha1"),
+    ("arm.FEAT_SHA256", "sha2"),
+    ("arm.FEAT_SHA512", "sha512"),
+    ("arm.FEAT_SHA3", "sha3"),
+    ("arm.FEAT_LSE", "atomics"),
+    ("arm.FEAT_LSE2", "uscat"),
+    ("arm.FEAT_RDM", "asimdrdm"),
+    ("arm.FEAT_DotProd", "asimddp"),
+    ("arm.FEAT_FHM", "asimdfhm"),
+    ("arm.FEAT_FP16", "fphp asimdhp"),
+    ("arm.FEAT_JSCVT", "jscvt"),
+    ("arm.FEAT_FCMA", "fcma"),
+    ("arm.FEAT_LRCPC", "lrcpc"),
+    ("arm.FEAT_LRCPC2", "ilrcpc"),
+    ("arm.FEAT_FlagM", "flagm"),
+    ("arm.FEAT_FlagM2", "flagm2"),
+    ("arm.FEAT_DIT", "dit"),
+    ("arm.FEAT_SSBS", "ssbs"),
+    ("arm.FEAT_SB", "sb"),
+    ("arm.FEAT_PAuth", "paca pacg"),
+    ("arm.FEAT_DPB", "dcpop"),
+    ("arm.FEAT_DPB2", "dcpodp"),
+    ("arm.FEAT_FRINTTS", "frint"),
+    ("arm.FEAT_BF16", "bf16"),
+    ("arm.FEAT_I8MM", "i8mm"),
+    ("arm.FEAT_ECV", "ecv"),
+    ("arm.FEAT_BTI", "bti"),
+    ("arm.FEAT_AFP", "afp"),
+    ("arm.FEAT_RPRES", "rpres"),
+    ("arm.FEAT_WFxT", "wfxt"),
+    ("arm.FEAT_EBF16", "ebf16"),
+    ("arm.FEAT_CSSC", "cssc"),
+    ("arm.FEAT_SME", "sme"),
+    ("arm.FEAT_SME2", "sme2"),
+    ("arm.FEAT_SME_F64F64", "smef64f64"),
+    ("arm.FEAT_SME_I16I64", "smei16i64"),
+];
+
+/// Maps the `hw.cpufamily` values of Apple Silicon to the name of the corresponding core. These
+/// values are defined in `mach/machine.h` of the XNU kernel.
+const APPLE_CPU_FAMILIES: &[(u32, &str)] = &[
+    (0x1b588bb3, "m1"), // Firestorm/Icestorm
+    (0xda33d83d, "m2"), // Avalanche/Blizzard
+    (0xfa33415e, "m3"), // Ibiza
+    (0x5f4dea93, "m3"), // Lobos
+    (0x72015832, "m3"), // Palma
+    (0x6f5129ac, "m4"), // Donan
+    (0x17d5b93a, "m4"), // Brava
+];
+
+/// Apple Silicon cores in the order they were released.
+const APPLE_MODELS: &[&str] = &["m1", "m2", "m3", "m4"];
+
+/// Determines the Apple Silicon core of a macOS machine.
+///
+/// The CPU family is the most reliable source, followed by the presence of features that were
+/// introduced with a specific generation, and lastly the brand string. If the core is not in the
+/// database, the newest known core that precedes it is returned instead.
+fn detect_apple_model<S: SysCtlProvider>(
+    sysctl: &S,
+    targets: &HashMap<String, Arc<Microarchitecture>>,
+) -> &'static str {
+    let has_feature = |name: &str| sysctl_enabled(sysctl, &format!("hw.optional.arm.{name}"));
+
+    let from_family = sysctl
+        .sysctl("hw.cpufamily")
+        .ok()
+        .and_then(|family| family.trim().parse::<i64>().ok())
+        .and_then(|family| {
+            APPLE_CPU_FAMILIES
+                .iter()
+                .find(|(value, _)| *value == family as u32)
+                .map(|(_, model)| *model)
+        });
+
+    let from_features = || {
+        if has_feature("FEAT_SME") {
+            Some("m4")
+        } else if has_feature("FEAT_BF16") {
+            Some("m2")
+        } else if has_feature("FEAT_LSE") {
+            Some("m1")
+        } else {
+            None
+        }
+    };
+
+    let from_brand = || {
+        let brand = sysctl
+            .sysctl("machdep.cpu.brand_string")
+            .ok()?
+            .to_lowercase();
+        APPLE_MODELS
+            .iter()
+            .rev()
+            .find(|model| brand.split_whitespace().any(|word| word == **model))
+            .copied()
+            .or_else(|| brand.contains("apple").then_some("m1"))
+    };
+
+    let Some(model) = from_family.or_else(from_features).or_else(from_brand) else {
+        return "unknown";
+    };
+
+    APPLE_MODELS
+        .iter()
+        .take_while(|known| **known != model)
+        .chain(std::iter::once(&model))
+        .filter(|known| targets.contains_key(**known))
+        .last()
+        .copied()
+        .unwrap_or(model)
+}
+
+/// Returns the names of the enabled entries in `/proc/sys/fs/binfmt_misc`.
+pub(super) fn read_binfmt_interpreters() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/proc/sys/fs/binfmt_misc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            std::fs::read_to_string(entry.path())
+                .is_ok_and(|contents| contents.lines().next() == Some("enabled"))
+        })
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+/// Maps the brand string of a server processor to its micro architecture. The generation is
+/// encoded in the model number, e.g. the `3` in `Xeon(R) Platinum 8375C` and `EPYC 7763`.
+fn x86_brand_microarchitecture(brand: &str) -> Option<&'static str> {
+    let model_number = |product: &str| {
+        let mut words = brand.split_whitespace();
+        words.find(|word| word.starts_with(product))?;
+        words.next().map(str::as_bytes)
+    };
+
+    if let Some(number) = model_number("EPYC") {
+        return match number.get(3)? {
+            b'1' => Some("zen"),
+            b'2' => Some("zen2"),
+            b'3' => Some("zen3"),
+            b'4' => Some("zen4"),
+            b'5' => Some("zen5"),
+            _ => None,
+        };
+    }
+
+    if brand.contains("Xeon") {
+        let number = ["Platinum", "Gold", "Silver", "Bronze"]
+            .iter()
+            .find_map(|tier| model_number(tier))?;
+        // The `H` and `HL` parts of the third generation are Cooper Lake, which has no target of
+        // its own and extends Cascade Lake by bfloat16.
+        let cooper_lake = number.ends_with(b"H") || number.ends_with(b"HL");
+        return match number.get(1)? {
+            b'1' => Some("skylake_avx512"),
+            b'2' => Some("cascadelake"),
+            b'3' if cooper_lake => Some("cascadelake"),
+            b'3' => Some("icelake"),
+            b'4' | b'5' => Some("sapphirerapids"),
+            _ => None,
+        };
+    }
+
+    None
+}
+
 fn compare_microarchitectures(a: &Microarchitecture, b: &Microarchitecture) -> Ordering {
     let ancestors_a = a.ancestors().len();
     let ancestors_b = b.ancestors().len();
@@ -262,20 +823,37 @@ fn compare_microarchitectures(a: &Microarchitecture, b: &Microarchitecture) -> O
     let features_a = a.features.len();
     let features_b = b.features.len();
 
+    // Targets are kept in a map, so ties are broken by name to make the result deterministic. The
+    // name that sorts first is preferred.
     ancestors_a
         .cmp(&ancestors_b)
         .then(features_a.cmp(&features_b))
+        .then_with(|| b.name.cmp(&a.name))
 }
 
-struct TargetDetector<S, C> {
+/// Detects the micro architecture of a host. By default the machine this is running on is probed,
+/// but every source of information can be replaced, e.g. to replay a [`super::ProbeSnapshot`].
+pub struct TargetDetector<S, C, H> {
     target_os: Option<String>,
     target_arch: Option<String>,
     cpu_info: Option<ProcCpuInfo>,
     cpuid_provider: C,
-    sysctl_provider: S,
+    pub(super) sysctl_provider: S,
+    hwcap_provider: H,
+    target_override: Option<String>,
+    check_target_override: bool,
+    binfmt_interpreters: Option<Vec<String>>,
+    database: Option<Arc<TargetDatabase>>,
 }
 
-impl TargetDetector<MachineSysCtlProvider, MachineCpuIdProvider> {
+impl Default for TargetDetector<MachineSysCtlProvider, MachineCpuIdProvider, MachineHwCapProvider> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TargetDetector<MachineSysCtlProvider, MachineCpuIdProvider, MachineHwCapProvider> {
+    /// Constructs a detector that probes the machine this is running on.
     pub fn new() -> Self {
         Self {
             target_os: None,
@@ -283,34 +861,87 @@ impl TargetDetector<MachineSysCtlProvider, MachineCpuIdProvider> {
             cpu_info: None,
             cpuid_provider: MachineCpuIdProvider::default(),
             sysctl_provider: MachineSysCtlProvider::default(),
+            hwcap_provider: MachineHwCapProvider::default(),
+            target_override: None,
+            check_target_override: false,
+            binfmt_interpreters: None,
+            database: None,
+        }
+    }
+
+    /// Applies the target override from the [`HOST_TARGET_ENV`] and [`CHECK_HOST_TARGET_ENV`]
+    /// environment variables.
+    fn with_env_override(self) -> Self {
+        match std::env::var(HOST_TARGET_ENV) {
+            Ok(name) if !name.is_empty() => {
+                let check = std::env::var(CHECK_HOST_TARGET_ENV)
+                    .is_ok_and(|value| !matches!(value.as_str(), "" | "0" | "false"));
+                self.with_target_override(&name, check)
+            }
+            _ => self,
         }
     }
 }
 
-impl<S: SysCtlProvider, C: CpuIdProvider> TargetDetector<S, C> {
+impl<S: SysCtlProvider, C: CpuIdProvider, H: HwCapProvider> TargetDetector<S, C, H> {
+    /// Replaces the source of `sysctl` values, used on macOS.
     pub fn with_sysctl_provider<O: SysCtlProvider>(
         self,
         sysctl_provider: O,
-    ) -> TargetDetector<O, C> {
+    ) -> TargetDetector<O, C, H> {
         TargetDetector {
             target_os: self.target_os,
             target_arch: self.target_arch,
             cpu_info: self.cpu_info,
             cpuid_provider: self.cpuid_provider,
             sysctl_provider,
+            hwcap_provider: self.hwcap_provider,
+            target_override: self.target_override,
+            check_target_override: self.check_target_override,
+            binfmt_interpreters: self.binfmt_interpreters,
+            database: self.database,
         }
     }
 
-    pub fn with_cpuid_provider<O: CpuIdProvider>(self, cpuid_provider: O) -> TargetDetector<S, O> {
+    /// Replaces the source of `CPUID` results, used on Windows.
+    pub fn with_cpuid_provider<O: CpuIdProvider>(
+        self,
+        cpuid_provider: O,
+    ) -> TargetDetector<S, O, H> {
         TargetDetector {
             target_os: self.target_os,
             target_arch: self.target_arch,
             cpu_info: self.cpu_info,
             cpuid_provider,
             sysctl_provider: self.sysctl_provider,
+            hwcap_provider: self.hwcap_provider,
+            target_override: self.target_override,
+            check_target_override: self.check_target_override,
+            binfmt_interpreters: self.binfmt_interpreters,
+            database: self.database,
+        }
+    }
+
+    /// Replaces the source of hardware capabilities, used on Linux.
+    pub fn with_hwcap_provider<O: HwCapProvider>(
+        self,
+        hwcap_provider: O,
+    ) -> TargetDetector<S, C, O> {
+        TargetDetector {
+            target_os: self.target_os,
+            target_arch: self.target_arch,
+            cpu_info: self.cpu_info,
+            cpuid_provider: self.cpuid_provider,
+            sysctl_provider: self.sysctl_provider,
+            hwcap_provider,
+            target_override: self.target_override,
+            check_target_override: self.check_target_override,
+            binfmt_interpreters: self.binfmt_interpreters,
+            database: self.database,
         }
     }
 
+    /// Sets the operating system, as in [`std::env::consts::OS`].
     pub fn with_target_os(self, target_os: &str) -> Self {
         Self {
             target_os: Some(target_os.to_string()),
@@ -318,6 +949,7 @@ impl<S: SysCtlProvider, C: CpuIdProvider> TargetDetector<S, C> {
         }
     }
 
+    /// Sets the architecture instead of querying it from the operating system.
     pub fn with_target_arch(self, target_arch: &str) -> Self {
         Self {
             target_arch: Some(target_arch.to_string()),
@@ -325,6 +957,7 @@ impl<S: SysCtlProvider, C: CpuIdProvider> TargetDetector<S, C> {
         }
     }
 
+    /// Sets the contents of `/proc/cpuinfo` instead of reading it, used on Linux.
     pub fn with_proc_cpu_info(self, proc_cpu_info: ProcCpuInfo) -> Self {
         Self {
             cpu_info: Some(proc_cpu_info),
@@ -332,16 +965,150 @@ impl<S: SysCtlProvider, C: CpuIdProvider> TargetDetector<S, C> {
         }
     }
 
-    pub fn detect(self) -> Result<Arc<Microarchitecture>, UnsupportedMicroarchitecture> {
-        let os = self.target_os.as_deref().unwrap_or(std::env::consts::OS);
+    /// Detects the targets of the given database instead of the default one.
+    pub fn with_database(self, database: Arc<TargetDatabase>) -> Self {
+        Self {
+            database: Some(database),
+            ..self
+        }
+    }
+
+    /// Sets the enabled `binfmt_misc` interpreters instead of reading them from
+    /// `/proc/sys/fs/binfmt_misc`, used on Linux to detect emulation.
+    pub fn with_binfmt_interpreters(self, interpreters: Vec<String>) -> Self {
+        Self {
+            binfmt_interpreters: Some(interpreters),
+            ..self
+        }
+    }
+
+    /// Returns the enabled `binfmt_misc` interpreters, read from the system if they were not set.
+    pub(super) fn binfmt_interpreters(&self) -> Cow<'_, [String]> {
+        match &self.binfmt_interpreters {
+            Some(interpreters) => Cow::Borrowed(interpreters),
+            None => Cow::Owned(read_binfmt_interpreters()),
+        }
+    }
+
+    /// Reports the target with the given name instead of the detected one. If `check` is true, the
+    /// target must be compatible with the detected host.
+    pub fn with_target_override(self, name: &str, check: bool) -> Self {
+        Self {
+            target_override: Some(name.to_string()),
+            check_target_override: check,
+            ..self
+        }
+    }
+
+    /// Detects the micro architecture of the host.
+    pub fn detect(self) -> Result<Arc<Microarchitecture>, DetectError> {
+        self.detect_info().map(|info| info.target)
+    }
+
+    /// Detects the micro architecture of the host together with the detected features.
+    pub fn detect_info(self) -> Result<HostInfo, DetectError> {
+        match &self.target_override {
+            Some(name) => self.detect_override(name),
+            None => self.detect_info_with(false),
+        }
+    }
+
+    /// Detects the host like [`Self::detect_info`], but instead of failing degrades to a generic
+    /// micro architecture with the detected features. A target override that names an unknown
+    /// target is ignored, and so is one the host is not compatible with if compatibility is
+    /// checked, see [`Self::with_target_override`].
+    pub fn detect_info_or_generic(self) -> HostInfo {
+        if let Some(info) = self
+            .target_override
+            .as_deref()
+            .and_then(|name| self.detect_override(name).ok())
+        {
+            return info;
+        }
+
+        self.detect_info_with(true).unwrap_or_else(|_| {
+            let arch = target_architecture_compiler();
+            HostInfo::generic(
+                arch,
+                Microarchitecture::generic(arch),
+                Fallback::CompilerArchitecture,
+            )
+        })
+    }
+
+    /// Reports the target with the given name as the host, see [`Self::with_target_override`].
+    fn detect_override(&self, name: &str) -> Result<HostInfo, DetectError> {
+        // The override must name a known target.
+        let target = self
+            .database()?
+            .get(name)
+            .cloned()
+            .ok_or(DetectError::Unsupported)?;
+
+        if !self.check_target_override {
+            return Ok(HostInfo {
+                vendor: target.vendor.clone(),
+                features: target.all_features(),
+                target,
+                fallback: None,
+                hypervisor: None,
+                brand_mismatch: None,
+            });
+        }
+
+        // The host must be able to run code built for the override.
+        let host = self.detect_info_with(false)?;
+        if host.target != target && !host.target.decendent_of(&target) {
+            return Err(DetectError::Unsupported);
+        }
+        Ok(HostInfo { target, ..host })
+    }
+
+    pub(super) fn detect_info_with(&self, generic_fallback: bool) -> Result<HostInfo, DetectError> {
+        let os = self.os();
+        match self.machine_architecture(os) {
+            Ok(arch) => self.detect_architecture(os, &arch, generic_fallback, None),
+            Err(_) if generic_fallback => self.detect_architecture(
+                os,
+                target_architecture_compiler(),
+                generic_fallback,
+                Some(Fallback::CompilerArchitecture),
+            ),
+            Err(_) => Err(DetectError::Unsupported),
+        }
+    }
+
+    /// Returns the database to detect the targets of, the default one if none was set.
+    fn database(&self) -> Result<&Arc<TargetDatabase>, LoadError> {
+        match &self.database {
+            Some(database) => Ok(database),
+            None => TargetDatabase::try_global(),
+        }
+    }
+
+    /// Returns the contents of `/proc/cpuinfo`, read from the system if they were not set.
+    pub(super) fn proc_cpu_info(&self) -> Cow<'_, ProcCpuInfo> {
+        match &self.cpu_info {
+            Some(cpu_info) => Cow::Borrowed(cpu_info),
+            None => Cow::Owned(ProcCpuInfo::from_proc_info().unwrap_or_default()),
+        }
+    }
+
+    /// Returns the operating system to detect the micro architecture for.
+    pub(super) fn os(&self) -> &str {
+        self.target_os.as_deref().unwrap_or(std::env::consts::OS)
+    }
 
-        // Determine the architecture of the machine based on the operating system.
-        let target_arch_uname;
-        let target_arch = match (os, &self.target_arch) {
+    /// Determines the architecture of the machine based on the operating system.
+    pub(super) fn machine_architecture(&self, os: &str) -> std::io::Result<String> {
+        Ok(match (os, &self.target_arch) {
             ("linux", None) => {
-                target_arch_uname =
-                    target_architecture_uname().map_err(|_| UnsupportedMicroarchitecture)?;
-                &target_arch_uname
+                // A 32-bit process on a 64-bit kernel can only make use of 32-bit targets.
+                if target_architecture_compiler() == "x86" {
+                    String::from("x86")
+                } else {
+                    target_architecture_uname()?
+                }
             }
             ("macos", _) => {
                 // On macOS, it might happen that we are on an M1 but running in Rosetta. In that
@@ -352,44 +1119,104 @@ impl<S: SysCtlProvider, C: CpuIdProvider> TargetDetector<S, C> {
                     .unwrap_or_default()
                     .contains("Apple")
                 {
-                    "aarch64"
+                    String::from("aarch64")
                 } else {
-                    "x86_64"
+                    String::from("x86_64")
                 }
             }
-            (_, Some(arch)) => arch.as_str(),
-            (_, None) => target_architecture_compiler(),
+            (_, Some(arch)) => arch.clone(),
+            (_, None) => target_architecture_compiler().to_string(),
+        })
+    }
+
+    /// Detects the micro architecture of the host for the given architecture.
+    pub(super) fn detect_architecture(
+        &self,
+        os: &str,
+        target_arch: &str,
+        generic_fallback: bool,
+        mut fallback: Option<Fallback>,
+    ) -> Result<HostInfo, DetectError> {
+        let target_arch = normalize_architecture(target_arch);
+
+        let database = match self.database() {
+            Ok(database) => database,
+            Err(_) if generic_fallback => {
+                return Ok(HostInfo::generic(
+                    target_arch,
+                    Microarchitecture::generic(target_arch),
+                    Fallback::DatabaseUnavailable,
+                ))
+            }
+            Err(err) => return Err(DetectError::Load(err)),
+        };
+        let targets = database.targets();
+        let cpu_info = match os {
+            "linux" => self.proc_cpu_info(),
+            _ => Cow::Owned(ProcCpuInfo::default()),
+        };
+
+        // Degrades to a generic micro architecture of the detected architecture if requested.
+        let degrade = |fallback: Fallback, detected_arch: Microarchitecture| {
+            if generic_fallback {
+                Ok(HostInfo {
+                    hypervisor: self.detect_hypervisor(os, target_arch, &detected_arch.features),
+                    ..HostInfo::generic(target_arch, detected_arch, fallback)
+                })
+            } else {
+                Err(DetectError::Unsupported)
+            }
         };
 
         // Detect the architecture based on the operating system.
         let detected_arch = match os {
-            "linux" => {
-                if let Some(cpu_info) = self.cpu_info.or_else(|| ProcCpuInfo::from_proc_info().ok())
-                {
-                    detect_linux(target_arch, &cpu_info)
-                } else {
-                    Microarchitecture::generic(target_arch)
+            "linux" => detect_linux(
+                target_arch,
+                &cpu_info,
+                &self.hwcap_provider,
+                database.schema(),
+            ),
+            "macos" => detect_macos(target_arch, &self.sysctl_provider, database),
+            "windows" => {
+                match detect_windows(target_arch, &self.cpuid_provider, database.cpuid_schema()) {
+                    Ok(detected_arch) => detected_arch,
+                    Err(_) => {
+                        return degrade(
+                            Fallback::UnknownArchitecture,
+                            Microarchitecture::generic(target_arch),
+                        )
+                    }
                 }
             }
-            "macos" => detect_macos(target_arch, &self.sysctl_provider),
-            "windows" => detect_windows(target_arch, &self.cpuid_provider)?,
+            _ if generic_fallback => {
+                // Without a way to query the CPU, only the architecture itself is known.
+                fallback = Some(Fallback::UnsupportedOperatingSystem);
+                Microarchitecture::generic(target_arch)
+            }
             _ => {
-                return Err(UnsupportedMicroarchitecture);
+                return Err(DetectError::Unsupported);
             }
         };
 
         // Determine compatible targets based on the architecture.
         let compatible_targets = match target_arch {
-            "aarch64" => compatible_microarchitectures_for_aarch64(&detected_arch, os == "macos"),
-            "ppc64" | "ppc64le" => {
-                compatible_microarchitectures_for_ppc64(&detected_arch, target_arch == "ppc64le")
-            }
-            "riscv64" => compatible_microarchitectures_for_riscv64(&detected_arch),
-            "x86_64" | "x86" => compatible_microarchitectures_for_x86_64(&detected_arch),
-            _ => vec![Microarchitecture::known_targets()
-                .get(target_arch)
-                .ok_or(UnsupportedMicroarchitecture)?
-                .clone()],
+            "aarch64" => {
+                compatible_microarchitectures_for_aarch64(targets, &detected_arch, os == "macos")
+            }
+            "ppc64" | "ppc64le" => compatible_microarchitectures_for_ppc64(
+                targets,
+                &detected_arch,
+                target_arch == "ppc64le",
+            ),
+            "riscv64" => compatible_microarchitectures_for_riscv64(targets, &detected_arch),
+            "loongarch64" => compatible_microarchitectures_for_loongarch64(targets, &detected_arch),
+            "s390x" => compatible_microarchitectures_for_s390x(targets, &detected_arch),
+            "x86_64" => compatible_microarchitectures_for_x86_64(targets, &detected_arch),
+            "x86" => compatible_microarchitectures_for_x86(targets, &detected_arch),
+            _ => match targets.get(target_arch) {
+                Some(target) => vec![target.clone()],
+                None => return degrade(Fallback::UnknownArchitecture, detected_arch),
+            },
         };
 
         // Find the best generic candidates
@@ -400,7 +1227,7 @@ impl<S: SysCtlProvider, C: CpuIdProvider> TargetDetector<S, C> {
             .last()
         else {
             // If there is no matching generic candidate then
-            return Err(UnsupportedMicroarchitecture);
+            return degrade(Fallback::NoCompatibleTarget, detected_arch);
         };
 
         // Filter the candidates to be descendant of the best generic candidate. This is to avoid that
@@ -413,42 +1240,225 @@ impl<S: SysCtlProvider, C: CpuIdProvider> TargetDetector<S, C> {
 
         // Resort the matching candidates and fall back to the best generic candidate if there is no
         // matching non-generic candidate.
-        Ok(best_candidates
+        let target = best_candidates
             .into_iter()
             .sorted_by(|a, b| compare_microarchitectures(a, b))
             .last()
             .unwrap_or(best_generic_candidate)
-            .clone())
+            .clone();
+
+        // Hypervisors may mask features, which shows if the brand implies a more capable target.
+        let brand_mismatch = match target_arch {
+            "x86_64" | "x86" => self
+                .x86_brand(os, &cpu_info, database.cpuid_schema())
+                .as_deref()
+                .and_then(x86_brand_microarchitecture)
+                .and_then(|name| targets.get(name))
+                .filter(|expected| target != **expected && !target.decendent_of(expected))
+                .cloned(),
+            _ => None,
+        };
+
+        Ok(HostInfo {
+            target,
+            vendor: detected_arch.vendor,
+            hypervisor: self.detect_hypervisor(os, target_arch, &detected_arch.features),
+            features: detected_arch.features,
+            fallback,
+            brand_mismatch,
+        })
+    }
+
+    /// Returns the vendor signature of the hypervisor if the host is a virtual machine. The
+    /// signature is empty if it is unknown.
+    fn detect_hypervisor(
+        &self,
+        os: &str,
+        target_arch: &str,
+        features: &HashSet<String>,
+    ) -> Option<String> {
+        if matches!(target_arch, "x86_64" | "x86") {
+            if let Some(vendor) = CpuId::hypervisor(&self.cpuid_provider) {
+                return Some(vendor);
+            }
+        }
+
+        let virtualized = features.contains("hypervisor")
+            || (os == "macos" && sysctl_enabled(&self.sysctl_provider, "kern.hv_vmm_present"));
+        virtualized.then(String::new)
+    }
+
+    /// Returns the brand string of an x86 CPU.
+    fn x86_brand(
+        &self,
+        os: &str,
+        cpu_info: &ProcCpuInfo,
+        cpuid_schema: &CpuIdSchema,
+    ) -> Option<String> {
+        match os {
+            "linux" => cpu_info.get("model name").map(str::to_string),
+            "macos" => self.sysctl_provider.sysctl("machdep.cpu.brand_string").ok(),
+            _ => CpuId::brand(&self.cpuid_provider, cpuid_schema),
+        }
+    }
+}
+
+/// Describes why detection degraded to a less specific micro architecture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback {
+    /// The architecture could not be queried from the operating system, the architecture this
+    /// crate was compiled for is assumed instead.
+    CompilerArchitecture,
+
+    /// There is no way to query the CPU on this operating system, only the architecture is known.
+    UnsupportedOperatingSystem,
+
+    /// The database does not contain any micro architecture for the architecture of the host.
+    UnknownArchitecture,
+
+    /// None of the generic micro architectures of the architecture is compatible with the host.
+    NoCompatibleTarget,
+
+    /// The database of micro architectures could not be loaded, see
+    /// [`TargetDatabase::try_global`].
+    DatabaseUnavailable,
+}
+
+/// The detected micro architecture of the host together with the information it was derived from.
+#[derive(Debug, Clone)]
+pub struct HostInfo {
+    target: Arc<Microarchitecture>,
+    vendor: String,
+    features: HashSet<String>,
+    fallback: Option<Fallback>,
+    hypervisor: Option<String>,
+    brand_mismatch: Option<Arc<Microarchitecture>>,
+}
+
+impl HostInfo {
+    /// Constructs the information of a host for which no known micro architecture was found. The
+    /// target is a generic micro architecture that carries the detected features.
+    fn generic(arch: &str, detected_arch: Microarchitecture, fallback: Fallback) -> Self {
+        Self {
+            target: Arc::new(Microarchitecture {
+                features: detected_arch.features.clone(),
+                ..Microarchitecture::generic(arch)
+            }),
+            vendor: detected_arch.vendor,
+            features: detected_arch.features,
+            fallback: Some(fallback),
+            hypervisor: None,
+            brand_mismatch: None,
+        }
+    }
+
+    /// Returns the known micro architecture that best matches the host.
+    pub fn target(&self) -> &Arc<Microarchitecture> {
+        &self.target
+    }
+
+    /// Returns the vendor as reported by the host.
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+
+    /// Returns the features reported by the host. Unlike the features of [`Self::target`], these
+    /// also include features that are not required by any known micro architecture.
+    pub fn features(&self) -> &HashSet<String> {
+        &self.features
+    }
+
+    /// Returns why detection degraded to a less specific micro architecture, if it did.
+    pub fn fallback(&self) -> Option<Fallback> {
+        self.fallback
+    }
+
+    /// Returns the vendor signature of the hypervisor, e.g. `KVMKVMKVM`, if the host is a virtual
+    /// machine. The signature is empty if the hypervisor is not known.
+    pub fn hypervisor(&self) -> Option<&str> {
+        self.hypervisor.as_deref()
+    }
+
+    /// Returns the micro architecture implied by the brand string of the CPU if the detected
+    /// target falls short of it. This typically means a hypervisor masks features of the CPU.
+    pub fn brand_mismatch(&self) -> Option<&Arc<Microarchitecture>> {
+        self.brand_mismatch.as_ref()
     }
 }
 
+/// Environment variable that overrides the detected host target, e.g. `x86_64_v2`. The name must
+/// be one of [`Microarchitecture::known_targets`], otherwise detection fails.
+pub const HOST_TARGET_ENV: &str = "ARCHSPEC_HOST_TARGET";
+
+/// Environment variable that, when set to anything but `0` or `false`, requires the target from
+/// [`HOST_TARGET_ENV`] to be compatible with the actual host.
+pub const CHECK_HOST_TARGET_ENV: &str = "ARCHSPEC_CHECK_HOST_TARGET";
+
 /// Detects the host micro-architecture and returns it.
-pub fn host() -> Result<Arc<Microarchitecture>, UnsupportedMicroarchitecture> {
-    TargetDetector::new().detect()
+///
+/// The result can be overridden with the [`HOST_TARGET_ENV`] environment variable. The host is
+/// only detected once per process, see [`host_uncached`] to detect it again.
+pub fn host() -> Result<Arc<Microarchitecture>, DetectError> {
+    host_info().map(|info| info.target.clone())
+}
+
+/// Detects the host micro-architecture like [`host`], without caching the result.
+pub fn host_uncached() -> Result<Arc<Microarchitecture>, DetectError> {
+    host_info_uncached().map(|info| info.target)
+}
+
+/// Detects the host micro-architecture and returns it together with the detected features.
+///
+/// The result can be overridden with the [`HOST_TARGET_ENV`] environment variable. The host is
+/// only detected once per process, see [`host_info_uncached`] to detect it again.
+pub fn host_info() -> Result<&'static HostInfo, DetectError> {
+    static HOST_INFO: OnceLock<Result<HostInfo, DetectError>> = OnceLock::new();
+    cached(&HOST_INFO, host_info_uncached)
+}
+
+/// Returns the result of `detect`, which only runs the first time `cache` is used.
+fn cached<T>(
+    cache: &'static OnceLock<Result<T, DetectError>>,
+    detect: impl FnOnce() -> Result<T, DetectError>,
+) -> Result<&'static T, DetectError> {
+    cache.get_or_init(detect).as_ref().map_err(Clone::clone)
+}
+
+/// Detects the host micro-architecture like [`host_info`], without caching the result.
+pub fn host_info_uncached() -> Result<HostInfo, DetectError> {
+    TargetDetector::new().with_env_override().detect_info()
+}
+
+/// Detects the host micro-architecture like [`host_info`], but never fails. If no known micro
+/// architecture matches the host, a generic micro architecture for the architecture of the host
+/// is returned and [`HostInfo::fallback`] reports why.
+///
+/// Like [`host`], this applies the target override from [`HOST_TARGET_ENV`] if it names a known
+/// target. If [`CHECK_HOST_TARGET_ENV`] is set, an override the host is not compatible with is
+/// ignored, otherwise it is applied unchecked.
+pub fn host_info_or_generic() -> HostInfo {
+    TargetDetector::new()
+        .with_env_override()
+        .detect_info_or_generic()
 }
 
 #[allow(unused)]
 fn compatible_microarchitectures_for_aarch64(
+    targets: &HashMap<String, Arc<Microarchitecture>>,
     detected_info: &Microarchitecture,
     is_macos: bool,
 ) -> Vec<Arc<Microarchitecture>> {
-    let targets = Microarchitecture::known_targets();
-
     // Get the root micro-architecture for aarch64.
     let Some(arch_root) = targets.get("aarch64") else {
         return vec![];
     };
 
     // On macOS it seems impossible to get all the CPU features with sysctl info, but for
-    // ARM we can get the exact model
-    let macos_model = if is_macos {
-        match targets.get(&detected_info.name) {
-            None => return vec![],
-            model => model,
-        }
-    } else {
-        None
-    };
+    // ARM we can get the exact model. On Linux the model may be known from the part number.
+    let model = targets.get(&detected_info.name);
+    if is_macos && model.is_none() {
+        return vec![];
+    }
 
     // Find all targets that are decendants of the root architecture and are compatibile with the
     // detected micro-architecture.
@@ -468,10 +1478,15 @@ fn compatible_microarchitectures_for_aarch64(
                 return false;
             }
 
-            if let Some(macos_model) = macos_model {
-                target.as_ref() == macos_model.as_ref() || macos_model.decendent_of(target)
-            } else {
-                target.features.is_subset(&detected_info.features)
+            match model {
+                Some(model) if is_macos => {
+                    target.as_ref() == model.as_ref() || model.decendent_of(target)
+                }
+                Some(model) => {
+                    (target.as_ref() == model.as_ref() || model.decendent_of(target))
+                        && target.features.is_subset(&detected_info.features)
+                }
+                None => target.features.is_subset(&detected_info.features),
             }
         })
         .cloned()
@@ -480,11 +1495,10 @@ fn compatible_microarchitectures_for_aarch64(
 
 #[allow(unused)]
 fn compatible_microarchitectures_for_ppc64(
+    targets: &HashMap<String, Arc<Microarchitecture>>,
     detected_info: &Microarchitecture,
     little_endian: bool,
 ) -> Vec<Arc<Microarchitecture>> {
-    let targets = Microarchitecture::known_targets();
-
     let root_arch = if little_endian { "ppc64le" } else { "ppc64" };
 
     // Get the root micro-architecture.
@@ -506,10 +1520,9 @@ fn compatible_microarchitectures_for_ppc64(
 
 #[allow(unused)]
 fn compatible_microarchitectures_for_x86_64(
+    targets: &HashMap<String, Arc<Microarchitecture>>,
     detected_info: &Microarchitecture,
 ) -> Vec<Arc<Microarchitecture>> {
-    let targets = Microarchitecture::known_targets();
-
     // Get the root micro-architecture for x86_64.
     let Some(arch_root) = targets.get("x86_64") else {
         return vec![];
@@ -529,23 +1542,115 @@ fn compatible_microarchitectures_for_x86_64(
 }
 
 #[allow(unused)]
-fn compatible_microarchitectures_for_riscv64(
+fn compatible_microarchitectures_for_x86(
+    targets: &HashMap<String, Arc<Microarchitecture>>,
     detected_info: &Microarchitecture,
 ) -> Vec<Arc<Microarchitecture>> {
-    let targets = Microarchitecture::known_targets();
+    // Get the root micro-architecture for 32-bit x86.
+    let Some(arch_root) = targets.get("x86") else {
+        return vec![];
+    };
+
+    // The 32-bit targets require `sse3` which is reported as `pni` by Linux and CPUID.
+    let mut features = detected_info.features.clone();
+    if features.contains("pni") {
+        features.insert(String::from("sse3"));
+    }
+
+    // Find all targets that are decendants of the root architecture and are compatibile with the
+    // detected micro-architecture.
+    targets
+        .values()
+        .filter(|target| {
+            (target.as_ref() == arch_root.as_ref() || target.decendent_of(arch_root))
+                && (target.vendor == detected_info.vendor || target.vendor == "generic")
+                && target.features.is_subset(&features)
+        })
+        .cloned()
+        .collect()
+}
 
+#[allow(unused)]
+fn compatible_microarchitectures_for_riscv64(
+    targets: &HashMap<String, Arc<Microarchitecture>>,
+    detected_info: &Microarchitecture,
+) -> Vec<Arc<Microarchitecture>> {
     // Get the root micro-architecture for riscv64.
     let Some(arch_root) = targets.get("riscv64") else {
         return vec![];
     };
 
+    // If the core is known, only it and its ancestors are considered. Otherwise the generic
+    // targets, like the profiles, are matched by the detected ISA extensions.
+    let model = targets
+        .get(&detected_info.name)
+        .filter(|model| model.vendor != "generic");
+
+    // Find all targets that are descendants of the root architecture and are compatible with the
+    // detected micro-architecture.
+    targets
+        .values()
+        .filter(|target| {
+            (target.as_ref() == arch_root.as_ref() || target.decendent_of(arch_root))
+                && target.features.is_subset(&detected_info.features)
+                && match model {
+                    Some(model) => target.as_ref() == model.as_ref() || model.decendent_of(target),
+                    None => target.vendor == "generic",
+                }
+        })
+        .cloned()
+        .collect()
+}
+
+#[allow(unused)]
+fn compatible_microarchitectures_for_loongarch64(
+    targets: &HashMap<String, Arc<Microarchitecture>>,
+    detected_info: &Microarchitecture,
+) -> Vec<Arc<Microarchitecture>> {
+    // Get the root micro-architecture for loongarch64.
+    let Some(arch_root) = targets.get("loongarch64") else {
+        return vec![];
+    };
+
+    // If the core is known from the model name, only it and its ancestors are considered.
+    let model = targets.get(&detected_info.name);
+
     // Find all targets that are descendants of the root architecture and are compatible with the
     // detected micro-architecture.
     targets
         .values()
         .filter(|target| {
             (target.as_ref() == arch_root.as_ref() || target.decendent_of(arch_root))
-                && (target.name == detected_info.name || target.vendor == "generic")
+                && (target.vendor == detected_info.vendor || target.vendor == "generic")
+                && target.features.is_subset(&detected_info.features)
+                && match model {
+                    Some(model) => target.as_ref() == model.as_ref() || model.decendent_of(target),
+                    None => true,
+                }
+        })
+        .cloned()
+        .collect()
+}
+
+#[allow(unused)]
+fn compatible_microarchitectures_for_s390x(
+    targets: &HashMap<String, Arc<Microarchitecture>>,
+    detected_info: &Microarchitecture,
+) -> Vec<Arc<Microarchitecture>> {
+    // Get the root micro-architecture for s390x.
+    let Some(arch_root) = targets.get("s390x") else {
+        return vec![];
+    };
+
+    // Find all targets that are descendants of the root architecture and are compatible with the
+    // detected micro-architecture. If the machine type is unknown, the features decide.
+    targets
+        .values()
+        .filter(|target| {
+            (target.as_ref() == arch_root.as_ref() || target.decendent_of(arch_root))
+                && (target.vendor == detected_info.vendor || target.vendor == "generic")
+                && target.features.is_subset(&detected_info.features)
+                && (detected_info.generation == 0 || target.generation <= detected_info.generation)
         })
         .cloned()
         .collect()
@@ -553,13 +1658,16 @@ fn compatible_microarchitectures_for_riscv64(
 
 #[cfg(test)]
 mod tests {
-    use crate::cpu::detect::{ProcCpuInfo, SysCtlProvider};
-    use crate::cpu::Microarchitecture;
-    use crate::cpuid::{CpuIdProvider, CpuIdRegisters};
+    use crate::cpu::detect::{ArmCpuId, Fallback, ProcCpuInfo, SysCtlProvider};
+    use crate::cpu::hwcap::{HwCapProvider, HwCaps, AARCH64_HWCAP_CPUID};
+    use crate::cpu::{Emulation, Microarchitecture};
+    use crate::cpuid::{CpuId, CpuIdProvider, CpuIdRegisters};
     use itertools::Itertools;
     use rstest::rstest;
     use std::collections::HashMap;
     use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::OnceLock;
 
     #[test]
     fn check_host() {
@@ -587,11 +1695,15 @@ mod tests {
         // Read the contents of the file.
         let contents = std::fs::read_to_string(&path).unwrap();
 
-        let detector = super::TargetDetector::new().with_target_arch(architecture_family);
+        // The fixtures do not contain the auxiliary vector, and that of the machine running the
+        // tests does not apply to them, even if it has the same architecture.
+        let detector = super::TargetDetector::new()
+            .with_target_arch(architecture_family)
+            .with_hwcap_provider(MockHwCapProvider::default());
         let detected_target = match platform {
             "linux" | "bgq" => detector
                 .with_target_os("linux")
-                .with_proc_cpu_info(ProcCpuInfo::from_str(&contents))
+                .with_proc_cpu_info(ProcCpuInfo::parse(&contents))
                 .detect(),
             "darwin" => detector
                 .with_target_os("macos")
@@ -608,6 +1720,730 @@ mod tests {
         assert_eq!(detected_target.as_ref(), expected_target.as_ref());
     }
 
+    #[test]
+    fn test_avx512_disabled_by_os() {
+        let contents = std::fs::read_to_string("json/tests/targets/windows-cpuid-icelake").unwrap();
+
+        // x87, SSE and AVX state enabled, but none of the AVX-512 state.
+        let cpuid = MockCpuIdProvider::from_str(&contents).with_xcr0(0b111);
+        let detected = CpuId::detect(&cpuid, crate::schema::CpuIdSchema::schema());
+        assert!(detected.features.contains("avx2"));
+        assert!(!detected.features.iter().any(|f| f.starts_with("avx512")));
+
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("windows")
+            .with_target_arch("x86_64")
+            .with_cpuid_provider(cpuid)
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "skylake");
+
+        // No XSAVE support enabled at all, AVX has to be dropped as well.
+        let cpuid = MockCpuIdProvider::from_str(&contents).with_xcr0(0b11);
+        let detected = CpuId::detect(&cpuid, crate::schema::CpuIdSchema::schema());
+        assert!(detected.features.contains("sse4_2"));
+        assert!(!detected.features.iter().any(|f| f.starts_with("avx")));
+        assert!(!detected.features.contains("fma"));
+    }
+
+    #[test]
+    fn test_arm_cpu_id() {
+        let contents =
+            std::fs::read_to_string("json/tests/targets/linux-rhel9-neoverse_v2").unwrap();
+        let cpu_id = ProcCpuInfo::parse(&contents).arm_cpu_id().unwrap();
+        assert_eq!(
+            cpu_id,
+            ArmCpuId {
+                implementer: 0x41,
+                variant: 0,
+                architecture: 8,
+                part: 0xd4f,
+            }
+        );
+        assert_eq!(cpu_id.model(), Some("neoverse_v2"));
+
+        // Without a known part number the model is derived from the features alone.
+        let contents = contents.replace("0xd4f", "0xfff");
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("aarch64")
+            .with_proc_cpu_info(ProcCpuInfo::parse(&contents))
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "neoverse_n2");
+
+        // A known part number pins the model, even though the features would match a newer one.
+        let contents = contents.replace("0xfff", "0xd0c");
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("aarch64")
+            .with_proc_cpu_info(ProcCpuInfo::parse(&contents))
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "neoverse_n1");
+    }
+
+    #[test]
+    fn test_aarch64_hwcaps() {
+        // Recorded on a Neoverse N1 machine.
+        let hwcaps = MockHwCapProvider {
+            hwcaps: Some(HwCaps {
+                hwcap: 0x10119fff,
+                hwcap2: 0,
+            }),
+            midr_el1: Some(0x413fd0c1),
+        };
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("aarch64")
+            .with_proc_cpu_info(ProcCpuInfo::default())
+            .with_hwcap_provider(hwcaps)
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "neoverse_n1");
+
+        // Without access to MIDR_EL1 the vendor is unknown.
+        let hwcaps = MockHwCapProvider {
+            hwcaps: Some(HwCaps {
+                hwcap: 0x10119fff & !AARCH64_HWCAP_CPUID,
+                hwcap2: 0,
+            }),
+            midr_el1: None,
+        };
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("aarch64")
+            .with_proc_cpu_info(ProcCpuInfo::default())
+            .with_hwcap_provider(hwcaps)
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "aarch64");
+    }
+
+    #[test]
+    fn test_apple_models() {
+        let newest_known = |models: &[&'static str]| {
+            let targets = Microarchitecture::known_targets();
+            models
+                .iter()
+                .copied()
+                .find(|model| targets.contains_key(*model))
+                .unwrap()
+        };
+
+        // An M4 is identified by its CPU family, newer cores than the database knows fall back to
+        // the newest known core.
+        let sysctl = MemorySysCtlProvider::from_str(
+            "hw.cpufamily: 1867590060\nmachdep.cpu.brand_string: Apple M4",
+        );
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("macos")
+            .with_sysctl_provider(sysctl)
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), newest_known(&["m4", "m3", "m2"]));
+
+        // Without the CPU family, the features are used.
+        let sysctl = MemorySysCtlProvider::from_str(
+            "hw.optional.arm.FEAT_BF16: 1\nmachdep.cpu.brand_string: Apple processor",
+        );
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("macos")
+            .with_sysctl_provider(sysctl)
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "m2");
+
+        // The brand string is the last resort.
+        let sysctl = MemorySysCtlProvider::from_str("machdep.cpu.brand_string: Apple M3 Max");
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("macos")
+            .with_sysctl_provider(sysctl)
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), newest_known(&["m3", "m2"]));
+    }
+
+    #[test]
+    fn test_macos_aarch64_features() {
+        let contents = std::fs::read_to_string("json/tests/targets/darwin-monterey-m2").unwrap();
+        let info = super::TargetDetector::new()
+            .with_target_os("macos")
+            .with_sysctl_provider(MemorySysCtlProvider::from_str(&contents))
+            .detect_info()
+            .unwrap();
+        assert_eq!(info.target().name(), "m2");
+        assert_eq!(info.vendor(), "Apple");
+
+        // All features are detected, except those that only the Linux kernel reports and `dit`
+        // which this version of macOS does not expose.
+        let missing = info
+            .target()
+            .all_features()
+            .into_iter()
+            .filter(|feature| !info.features().contains(feature))
+            .sorted()
+            .collect_vec();
+        assert_eq!(missing, ["cpuid", "dit", "evtstrm"]);
+    }
+
+    #[test]
+    fn test_riscv_isa_features() {
+        let features = super::riscv_isa_features("rv64imafdch_zicsr_zifencei_zba_zbb");
+        assert_eq!(
+            features.iter().map(String::as_str).sorted().collect_vec(),
+            ["a", "c", "d", "f", "h", "i", "m", "zba", "zbb", "zicsr", "zifencei"]
+        );
+
+        let features = super::riscv_isa_features("rv64gcv_sstc_xtheadvector");
+        assert_eq!(
+            features.iter().map(String::as_str).sorted().collect_vec(),
+            [
+                "a",
+                "c",
+                "d",
+                "f",
+                "i",
+                "m",
+                "sstc",
+                "v",
+                "xtheadvector",
+                "zicsr",
+                "zifencei"
+            ]
+        );
+
+        let features = super::riscv_isa_features("rv64i2p1m2p0a2p1zicsr2p0_zba1p0");
+        assert_eq!(
+            features.iter().map(String::as_str).sorted().collect_vec(),
+            ["a", "i", "m", "zba", "zicsr", "zifencei"]
+        );
+
+        // Older kernels do not list the extensions that were split off from `i`.
+        let features = super::riscv_isa_features("rv64imafdc");
+        assert_eq!(
+            features.iter().map(String::as_str).sorted().collect_vec(),
+            ["a", "c", "d", "f", "i", "m", "zicsr", "zifencei"]
+        );
+    }
+
+    #[rstest]
+    #[case("rv64imafdc_zicsr_zifencei", "rva20u64")]
+    #[case("rv64imafdc", "rva20u64")]
+    #[case(
+        "rv64imafdcv_zicbom_zicboz_zicntr_zicond_zicsr_zifencei_zihintpause_zihpm_zfh_zfhmin_zca_zcd_zba_zbb_zbc_zbs_zkt_zve32f_zve32x_zve64d_zve64f_zve64x_zvfh_zvfhmin_zvkt",
+        "rva22u64"
+    )]
+    #[case(
+        "rv64imafdcvh_zicbom_zicbop_zicboz_zicntr_zicond_zicsr_zifencei_zihintntl_zihintpause_zihpm_zimop_zawrs_zfa_zfh_zfhmin_zca_zcb_zcd_zcmop_zba_zbb_zbs_zkt_zvbb_zvfhmin_zvkt",
+        "rva23u64"
+    )]
+    fn test_riscv_profiles(#[case] isa: &str, #[case] expected: &str) {
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("riscv64")
+            .with_proc_cpu_info(ProcCpuInfo::parse(&format!("isa : {isa}")))
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), expected);
+    }
+
+    #[test]
+    fn test_power_compatibility_mode() {
+        assert_eq!(
+            super::PowerCpu::parse("POWER8NVL (raw), altivec supported"),
+            super::PowerCpu {
+                generation: 8,
+                architected: false,
+                altivec: true,
+            }
+        );
+
+        // A POWER10 running in a partition restricted to the POWER9 ISA.
+        let hwcaps = || MockHwCapProvider {
+            hwcaps: Some(HwCaps {
+                hwcap: 0x5c0075c3,
+                hwcap2: 0xbef00000,
+            }),
+            midr_el1: None,
+        };
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("ppc64le")
+            .with_proc_cpu_info(ProcCpuInfo::parse("cpu : POWER10 (raw), altivec supported"))
+            .with_hwcap_provider(hwcaps())
+            .detect_info()
+            .unwrap();
+        assert_eq!(info.target().name(), "power9le");
+        for feature in ["altivec", "vsx", "arch_3_00", "ieee128"] {
+            assert!(info.features().contains(feature), "missing {}", feature);
+        }
+        assert!(!info.features().contains("mma"));
+
+        // In architected mode the generation of the name is trusted, even if the kernel does not
+        // report the ISA level of it.
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("ppc64le")
+            .with_proc_cpu_info(ProcCpuInfo::parse(
+                "cpu : POWER10 (architected), altivec supported",
+            ))
+            .with_hwcap_provider(hwcaps())
+            .detect_info()
+            .unwrap();
+        assert_eq!(info.target().name(), "power10le");
+    }
+
+    #[test]
+    fn test_x86_32bit() {
+        // A 32-bit process on a 64-bit CPU is limited to the 32-bit targets.
+        let contents =
+            std::fs::read_to_string("json/tests/targets/windows-cpuid-broadwell").unwrap();
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("windows")
+            .with_target_arch("x86")
+            .with_cpuid_provider(MockCpuIdProvider::from_str(&contents))
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "prescott");
+
+        let contents = std::fs::read_to_string("json/tests/targets/linux-rhel7-broadwell").unwrap();
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("i686")
+            .with_proc_cpu_info(ProcCpuInfo::parse(&contents))
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "prescott");
+
+        // A Pentium III running a 32-bit kernel.
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("i686")
+            .with_proc_cpu_info(ProcCpuInfo::parse(
+                "vendor_id : GenuineIntel\n\
+                 flags : fpu vme de pse tsc msr pae mce cx8 apic sep mtrr pge mca cmov pat pse36 mmx fxsr sse",
+            ))
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "pentium3");
+
+        // Other vendors only match the generic target.
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("i686")
+            .with_proc_cpu_info(ProcCpuInfo::parse(
+                "vendor_id : AuthenticAMD\nflags : fpu mmx sse sse2 pni",
+            ))
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), "x86");
+    }
+
+    #[rstest]
+    #[case(
+        "Model Name : Loongson-3A5000-HV\n\
+         Features : cpucfg lam ual fpu lsx lasx crc32 complex crypto lvz lbt_x86 lbt_arm lbt_mips",
+        "la464"
+    )]
+    #[case(
+        "Model Name : Loongson-3A6000\n\
+         Features : cpucfg lam ual fpu lsx lasx crc32 complex crypto ptw lspw lvz lbt_x86 lbt_arm lbt_mips",
+        "la664"
+    )]
+    #[case(
+        "Model Name : Loongson-3A6000\nFeatures : cpucfg lam ual fpu",
+        "loongarch64"
+    )]
+    fn test_loongarch64(#[case] cpu_info: &str, #[case] expected: &str) {
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("loongarch64")
+            .with_proc_cpu_info(ProcCpuInfo::parse(cpu_info))
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), expected);
+    }
+
+    #[rstest]
+    #[case("3906", "z14")]
+    #[case("8561", "z15")]
+    #[case("3931", "z16")]
+    #[case("9175", "z16")]
+    fn test_s390x(#[case] machine: &str, #[case] expected: &str) {
+        let cpu_info = format!(
+            "vendor_id       : IBM/S390\n\
+             # processors    : 1\n\
+             features\t: esan3 zarch stfle msa ldisp eimm dfp edat etf3eh highgprs te vx vxd vxe gs vxe2 vxp sort dflt vxp2 nnpa pcimio sie\n\
+             processor 0: version = 00,  identification = 0133E8,  machine = {}\n",
+            machine
+        );
+        let detected_target = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("s390x")
+            .with_proc_cpu_info(ProcCpuInfo::parse(&cpu_info))
+            .detect()
+            .unwrap();
+        assert_eq!(detected_target.name(), expected);
+    }
+
+    #[test]
+    fn test_host_cached() {
+        static CACHE: OnceLock<Result<usize, super::DetectError>> = OnceLock::new();
+        let detections = AtomicUsize::new(0);
+        let detect = || Ok(detections.fetch_add(1, Ordering::SeqCst));
+        let first = super::cached(&CACHE, detect).unwrap();
+        let second = super::cached(&CACHE, detect).unwrap();
+        assert_eq!(detections.load(Ordering::SeqCst), 1);
+        assert!(std::ptr::eq(first, second));
+
+        // The host is detected once, a failure is reported every time.
+        match (super::host_info(), super::host_info()) {
+            (Ok(first), Ok(second)) => assert!(std::ptr::eq(first, second)),
+            (Err(first), Err(second)) => assert_eq!(first, second),
+            results => panic!("detection results differ: {:?}", results),
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "powerpc64"))]
+    fn test_machine_hwcaps_of_other_architecture() {
+        // The auxiliary vector of this process must not be decoded as that of a POWER process.
+        let hwcaps = crate::cpu::MachineHwCapProvider::default();
+        assert_eq!(
+            hwcaps.hwcaps("ppc64le").unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_machine_sysctl_unsupported() {
+        let sysctl = super::MachineSysCtlProvider::default();
+        assert_eq!(
+            sysctl.sysctl("hw.cpufamily").unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+
+        // Detecting macOS elsewhere finds no features, but must not panic.
+        let _ = super::TargetDetector::new()
+            .with_target_os("macos")
+            .detect();
+    }
+
+    #[test]
+    fn test_generic_fallback() {
+        // There are no targets for this architecture.
+        let detector = || {
+            super::TargetDetector::new()
+                .with_target_os("linux")
+                .with_target_arch("hppa")
+                .with_proc_cpu_info(ProcCpuInfo::default())
+        };
+        assert!(detector().detect().is_err());
+        let info = detector().detect_info_or_generic();
+        assert_eq!(info.target().name(), "hppa");
+        assert_eq!(info.fallback(), Some(Fallback::UnknownArchitecture));
+
+        // A host that matches a known target does not report a fallback.
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("x86_64")
+            .with_proc_cpu_info(ProcCpuInfo::parse(
+                "vendor_id : GenuineIntel\nflags : fpu sse sse2",
+            ))
+            .detect_info_or_generic();
+        assert_eq!(info.target().name(), "x86_64");
+        assert_eq!(info.fallback(), None);
+
+        // Without a way to query the CPU only the architecture is known.
+        let info = super::TargetDetector::new()
+            .with_target_os("freebsd")
+            .with_target_arch("x86_64")
+            .detect_info_or_generic();
+        assert_eq!(info.target().name(), "x86_64");
+        assert_eq!(info.fallback(), Some(Fallback::UnsupportedOperatingSystem));
+    }
+
+    #[test]
+    fn test_target_override() {
+        let detector = || {
+            super::TargetDetector::new()
+                .with_target_os("linux")
+                .with_target_arch("x86_64")
+                .with_proc_cpu_info(ProcCpuInfo::parse(
+                    "vendor_id : GenuineIntel\nflags : fpu mmx sse sse2 pni ssse3 sse4_1 sse4_2 popcnt cx16 lahf_lm",
+                ))
+        };
+        assert_eq!(detector().detect().unwrap().name(), "nehalem");
+
+        // Lower targets are compatible with the host.
+        let info = detector()
+            .with_target_override("x86_64", true)
+            .detect_info()
+            .unwrap();
+        assert_eq!(info.target().name(), "x86_64");
+        assert_eq!(info.vendor(), "GenuineIntel");
+
+        // Higher targets are only accepted if compatibility is not checked.
+        assert!(detector()
+            .with_target_override("x86_64_v3", true)
+            .detect()
+            .is_err());
+        assert_eq!(
+            detector()
+                .with_target_override("x86_64_v3", false)
+                .detect()
+                .unwrap()
+                .name(),
+            "x86_64_v3"
+        );
+
+        // Unknown targets are rejected.
+        assert_eq!(
+            detector()
+                .with_target_override("not_a_target", false)
+                .detect()
+                .unwrap_err(),
+            super::DetectError::Unsupported
+        );
+
+        // With a generic fallback, valid overrides apply and invalid ones are ignored.
+        assert_eq!(
+            detector()
+                .with_target_override("x86_64_v2", true)
+                .detect_info_or_generic()
+                .target()
+                .name(),
+            "x86_64_v2"
+        );
+        assert_eq!(
+            detector()
+                .with_target_override("not_a_target", false)
+                .detect_info_or_generic()
+                .target()
+                .name(),
+            "nehalem"
+        );
+    }
+
+    #[test]
+    fn test_probe_snapshot() {
+        // Replaying a snapshot of this machine gives the same result as probing it.
+        let snapshot = crate::cpu::ProbeSnapshot::capture();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: crate::cpu::ProbeSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            snapshot
+                .detector()
+                .detect()
+                .ok()
+                .map(|target| target.name().to_string()),
+            super::TargetDetector::new()
+                .detect()
+                .ok()
+                .map(|target| target.name().to_string())
+        );
+        assert_eq!(
+            snapshot
+                .detector()
+                .detect_emulation()
+                .ok()
+                .map(|info| info.emulation()),
+            crate::cpu::host_emulation()
+                .ok()
+                .map(|info| info.emulation())
+        );
+
+        // Snapshots attached to a bug report only contain what detection reads.
+        let snapshot: crate::cpu::ProbeSnapshot = serde_json::from_str(
+            r#"{
+                "os": "linux",
+                "arch": "aarch64",
+                "hwcaps": { "hwcap": 269590527, "hwcap2": 0 },
+                "midr_el1": 1094701249
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(snapshot.detector().detect().unwrap().name(), "neoverse_n1");
+
+        // Emulation is detected from the recorded interpreters, not the ones of this machine.
+        let mut snapshot: crate::cpu::ProbeSnapshot = serde_json::from_str(
+            r#"{
+                "os": "linux",
+                "arch": "riscv64",
+                "proc_cpu_info": "isa : rv64imafdc",
+                "binfmt_interpreters": ["qemu-riscv64"]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            snapshot.detector().detect_emulation().unwrap().emulation(),
+            Some(Emulation::QemuUser)
+        );
+        snapshot.binfmt_interpreters.clear();
+        assert_eq!(
+            snapshot.detector().detect_emulation().unwrap().emulation(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_emulation() {
+        let x86_64_cpu_info = "vendor_id : GenuineIntel\n\
+            flags : fpu mmx sse sse2 pni ssse3 sse4_1 sse4_2 popcnt cx16 lahf_lm";
+
+        // The user-mode emulator of QEMU passes the cpuinfo of the machine through.
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("aarch64")
+            .with_proc_cpu_info(ProcCpuInfo::parse(x86_64_cpu_info))
+            .with_hwcap_provider(MockHwCapProvider::default())
+            .with_binfmt_interpreters(Vec::new())
+            .detect_emulation()
+            .unwrap();
+        assert_eq!(info.emulation(), Some(Emulation::QemuUser));
+        assert_eq!(info.process().target().name(), "aarch64");
+        assert_eq!(info.real().unwrap().target().name(), "nehalem");
+
+        // A 32-bit process on a 64-bit CPU is not emulated.
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("i686")
+            .with_proc_cpu_info(ProcCpuInfo::parse(x86_64_cpu_info))
+            .with_binfmt_interpreters(vec![String::from("qemu-aarch64")])
+            .detect_emulation()
+            .unwrap();
+        assert_eq!(info.emulation(), None);
+        assert_eq!(info.real().unwrap().target(), info.process().target());
+
+        // A registered interpreter for the architecture of the process.
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("riscv64")
+            .with_proc_cpu_info(ProcCpuInfo::default())
+            .with_binfmt_interpreters(vec![String::from("qemu-riscv64")])
+            .detect_emulation()
+            .unwrap();
+        assert_eq!(info.emulation(), Some(Emulation::QemuUser));
+        assert!(info.real().is_none());
+
+        // A virtual machine with a CPU model provided by QEMU.
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("x86_64")
+            .with_proc_cpu_info(ProcCpuInfo::parse(
+                "vendor_id : AuthenticAMD\nmodel name : QEMU Virtual CPU version 2.5+\nflags : fpu",
+            ))
+            .with_binfmt_interpreters(Vec::new())
+            .detect_emulation()
+            .unwrap();
+        assert_eq!(info.emulation(), Some(Emulation::QemuCpuModel));
+
+        // Rosetta translates x86_64 code on Apple silicon.
+        let info = super::TargetDetector::new()
+            .with_target_os("macos")
+            .with_sysctl_provider(MemorySysCtlProvider::from_str(
+                "hw.cpufamily: 458787763\n\
+                machdep.cpu.brand_string: Apple M1\n\
+                sysctl.proc_translated: 1",
+            ))
+            .detect_emulation()
+            .unwrap();
+        assert_eq!(info.emulation(), Some(Emulation::Rosetta));
+        assert_eq!(info.process().target().name(), "x86_64");
+        assert_eq!(info.real().unwrap().target().name(), "m1");
+    }
+
+    #[test]
+    fn test_hypervisor() {
+        let cpu_info = "vendor_id : GenuineIntel\n\
+            model name : Intel(R) Xeon(R) Platinum 8375C CPU @ 2.90GHz\n\
+            flags : fpu mmx sse sse2 pni ssse3 sse4_1 sse4_2 popcnt cx16 lahf_lm hypervisor";
+
+        // The vendor of the hypervisor is read from its CPUID leaf.
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("x86_64")
+            .with_proc_cpu_info(ProcCpuInfo::parse(cpu_info))
+            .with_cpuid_provider(MockCpuIdProvider::from_str(
+                "1, 0, 0, 0, 2147483648, 0\n1073741824, 0, 0, 1263359563, 1447775574, 77",
+            ))
+            .detect_info()
+            .unwrap();
+        assert_eq!(info.hypervisor(), Some("KVMKVMKVM"));
+
+        // The hypervisor hides the AVX-512 features of the Ice Lake CPU.
+        assert_eq!(info.target().name(), "nehalem");
+        assert_eq!(info.brand_mismatch().unwrap().name(), "icelake");
+
+        // Without the hypervisor bit, the flag in cpuinfo still shows the virtual machine.
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch("x86_64")
+            .with_proc_cpu_info(ProcCpuInfo::parse(cpu_info))
+            .with_cpuid_provider(MockCpuIdProvider::from_str("1, 0, 0, 0, 0, 0"))
+            .detect_info()
+            .unwrap();
+        assert_eq!(info.hypervisor(), Some(""));
+    }
+
+    #[rstest]
+    #[case(
+        "Intel(R) Xeon(R) Platinum 8175M CPU @ 2.50GHz",
+        Some("skylake_avx512")
+    )]
+    #[case("Intel(R) Xeon(R) Gold 6248R CPU @ 3.00GHz", Some("cascadelake"))]
+    #[case("Intel(R) Xeon(R) Platinum 8380H CPU @ 2.90GHz", Some("cascadelake"))]
+    #[case("Intel(R) Xeon(R) Platinum 8376HL CPU @ 2.60GHz", Some("cascadelake"))]
+    #[case("Intel(R) Xeon(R) Platinum 8375C CPU @ 2.90GHz", Some("icelake"))]
+    #[case("Intel(R) Xeon(R) Platinum 8488C", Some("sapphirerapids"))]
+    #[case("AMD EPYC 7R13 Processor", Some("zen3"))]
+    #[case("Intel(R) Core(TM) i7-8700 CPU @ 3.20GHz", None)]
+    fn test_x86_brand_microarchitecture(#[case] brand: &str, #[case] expected: Option<&str>) {
+        assert_eq!(super::x86_brand_microarchitecture(brand), expected);
+    }
+
+    #[rstest]
+    fn test_brand_consistent(#[files("json/tests/targets/linux-*")] path: PathBuf) {
+        let filename = path.file_name().unwrap().to_string_lossy();
+        let target = filename.rsplit('-').next().unwrap();
+        let family = Microarchitecture::known_targets()[target]
+            .family()
+            .name
+            .clone();
+
+        // The fixtures only contain /proc/cpuinfo, CPUID does not report a hypervisor.
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let info = super::TargetDetector::new()
+            .with_target_os("linux")
+            .with_target_arch(&family)
+            .with_proc_cpu_info(ProcCpuInfo::parse(&contents))
+            .with_hwcap_provider(MockHwCapProvider::default())
+            .with_cpuid_provider(MockCpuIdProvider::from_str("1, 0, 0, 0, 0, 0"))
+            .detect_info()
+            .unwrap();
+        assert!(info.brand_mismatch().is_none(), "{}", path.display());
+    }
+
+    #[derive(Default)]
+    struct MockHwCapProvider {
+        hwcaps: Option<HwCaps>,
+        midr_el1: Option<u64>,
+    }
+
+    impl HwCapProvider for MockHwCapProvider {
+        fn hwcaps(&self, _arch: &str) -> std::io::Result<HwCaps> {
+            self.hwcaps
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+        }
+
+        fn midr_el1(&self) -> std::io::Result<u64> {
+            self.midr_el1
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::Unsupported))
+        }
+    }
+
     struct MemorySysCtlProvider {
         contents: HashMap<String, String>,
     }
@@ -634,6 +2470,7 @@ mod tests {
 
     struct MockCpuIdProvider {
         contents: HashMap<(u32, u32), CpuIdRegisters>,
+        xcr0: Option<u64>,
     }
 
     impl MockCpuIdProvider {
@@ -647,7 +2484,17 @@ mod tests {
                     .unwrap();
                 contents.insert((leaf, subleaf), CpuIdRegisters { eax, ebx, ecx, edx });
             }
-            Self { contents }
+            Self {
+                contents,
+                xcr0: None,
+            }
+        }
+
+        pub fn with_xcr0(self, xcr0: u64) -> Self {
+            Self {
+                xcr0: Some(xcr0),
+                ..self
+            }
         }
     }
 
@@ -655,5 +2502,10 @@ mod tests {
         fn cpuid(&self, leaf: u32, subleaf: u32) -> CpuIdRegisters {
             self.contents.get(&(leaf, subleaf)).cloned().unwrap()
         }
+
+        fn xgetbv(&self, _xcr: u32) -> u64 {
+            // The recorded CPUID dumps do not contain XCR0, assume the OS enabled every state.
+            self.xcr0.unwrap_or(u64::MAX)
+        }
     }
 }
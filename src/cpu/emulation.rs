@@ -0,0 +1,163 @@
+//! Detection of processes that run on a CPU of another architecture, e.g. through Rosetta on
+//! macOS or the user-mode emulator of QEMU on Linux.
+
+use super::detect::{normalize_architecture, sysctl_enabled, SysCtlProvider, TargetDetector};
+use super::{CpuIdProvider, DetectError, HostInfo, HwCapProvider, ProcCpuInfo};
+
+/// How a process is run on a CPU it was not built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emulation {
+    /// Rosetta translates x86_64 code on Apple silicon.
+    Rosetta,
+
+    /// The user-mode emulator of QEMU runs the process, usually through `binfmt_misc`.
+    QemuUser,
+
+    /// The CPU is a model provided by QEMU, e.g. `QEMU Virtual CPU`. The whole machine is then
+    /// either emulated by QEMU or virtualized with KVM, which `/proc/cpuinfo` does not tell apart,
+    /// so the process may well run natively.
+    QemuCpuModel,
+}
+
+/// The micro architecture visible to the process together with the one of the machine.
+#[derive(Debug, Clone)]
+pub struct EmulationInfo {
+    emulation: Option<Emulation>,
+    process: HostInfo,
+    real: Option<HostInfo>,
+}
+
+impl EmulationInfo {
+    /// Returns how the process is emulated or translated, if it is.
+    pub fn emulation(&self) -> Option<Emulation> {
+        self.emulation
+    }
+
+    /// Returns the micro architecture as it is visible to the process.
+    pub fn process(&self) -> &HostInfo {
+        &self.process
+    }
+
+    /// Returns the micro architecture of the machine, if it can be determined.
+    pub fn real(&self) -> Option<&HostInfo> {
+        self.real.as_ref()
+    }
+}
+
+impl<S: SysCtlProvider, C: CpuIdProvider, H: HwCapProvider> TargetDetector<S, C, H> {
+    /// Detects whether the process is emulated or translated, and returns both the micro
+    /// architecture visible to the process and the one of the machine.
+    pub fn detect_emulation(self) -> Result<EmulationInfo, DetectError> {
+        let os = self.os();
+        match os {
+            "macos" => {
+                // The machine is already detected as Apple silicon, the process sees an x86_64 CPU.
+                let real = self.detect_info_with(false)?;
+                if !sysctl_enabled(&self.sysctl_provider, "sysctl.proc_translated") {
+                    return Ok(not_emulated(real));
+                }
+                Ok(EmulationInfo {
+                    emulation: Some(Emulation::Rosetta),
+                    process: self.detect_architecture(os, "x86_64", false, None)?,
+                    real: Some(real),
+                })
+            }
+            "linux" => {
+                let process = self.detect_info_with(false)?;
+                let process_arch = process.target().family().name().to_string();
+                let cpu_info = self.proc_cpu_info();
+
+                // A virtual machine of QEMU reports the CPU model it provides.
+                let model_name = cpu_info.get("model name").unwrap_or_default();
+                if model_name.starts_with("QEMU") {
+                    return Ok(EmulationInfo {
+                        emulation: Some(Emulation::QemuCpuModel),
+                        process,
+                        real: None,
+                    });
+                }
+
+                // The user-mode emulator passes the cpuinfo of the machine through.
+                if let Some(real_arch) = cpu_info_architecture(&cpu_info) {
+                    if !same_instruction_set(real_arch, &process_arch) {
+                        return Ok(EmulationInfo {
+                            emulation: Some(Emulation::QemuUser),
+                            real: self.detect_architecture(os, real_arch, false, None).ok(),
+                            process,
+                        });
+                    }
+                }
+
+                // An interpreter that is registered for the architecture of the process implies
+                // it is foreign to the machine.
+                let interpreter = match process_arch.as_str() {
+                    "x86" => String::from("qemu-i386"),
+                    arch => format!("qemu-{arch}"),
+                };
+                if self.binfmt_interpreters().contains(&interpreter) {
+                    return Ok(EmulationInfo {
+                        emulation: Some(Emulation::QemuUser),
+                        process,
+                        real: None,
+                    });
+                }
+
+                Ok(not_emulated(process))
+            }
+            _ => Ok(not_emulated(self.detect_info_with(false)?)),
+        }
+    }
+}
+
+/// Detects whether the current process is emulated or translated, see
+/// [`TargetDetector::detect_emulation`].
+pub fn host_emulation() -> Result<EmulationInfo, DetectError> {
+    TargetDetector::new().detect_emulation()
+}
+
+fn not_emulated(host: HostInfo) -> EmulationInfo {
+    EmulationInfo {
+        emulation: None,
+        real: Some(host.clone()),
+        process: host,
+    }
+}
+
+/// Infers the architecture of the machine from the keys and values in `/proc/cpuinfo`.
+fn cpu_info_architecture(cpu_info: &ProcCpuInfo) -> Option<&'static str> {
+    if cpu_info.get("vendor_id") == Some("IBM/S390") {
+        Some("s390x")
+    } else if cpu_info.get("vendor_id").is_some() && cpu_info.get("flags").is_some() {
+        Some("x86_64")
+    } else if cpu_info.get("CPU implementer").is_some() {
+        Some("aarch64")
+    } else if cpu_info
+        .get("cpu")
+        .is_some_and(|cpu| cpu.starts_with("POWER"))
+    {
+        Some("ppc64le")
+    } else if cpu_info
+        .get("isa")
+        .is_some_and(|isa| isa.starts_with("rv64"))
+    {
+        Some("riscv64")
+    } else if cpu_info
+        .get("Model Name")
+        .is_some_and(|model| model.starts_with("Loongson"))
+    {
+        Some("loongarch64")
+    } else {
+        None
+    }
+}
+
+/// Returns true if code for one architecture runs natively on the other, e.g. 32-bit x86 code on
+/// an x86_64 CPU.
+fn same_instruction_set(a: &str, b: &str) -> bool {
+    let family = |arch| match normalize_architecture(arch) {
+        "x86" => "x86_64",
+        "ppc64" => "ppc64le",
+        arch => arch,
+    };
+    family(a) == family(b)
+}
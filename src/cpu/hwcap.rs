@@ -0,0 +1,210 @@
+//! Detection of CPU features through the `AT_HWCAP` and `AT_HWCAP2` entries of the auxiliary
+//! vector on Linux. Unlike `/proc/cpuinfo` these are always available to a process, and on POWER
+//! they are the only source of features.
+
+use super::detect::{normalize_architecture, target_architecture_compiler};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// The `AT_HWCAP` and `AT_HWCAP2` entries of the auxiliary vector.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HwCaps {
+    pub hwcap: u64,
+    pub hwcap2: u64,
+}
+
+impl HwCaps {
+    /// Decodes the set bits into feature names using the tables for the respective entries. The
+    /// index of a name in a table is the bit it corresponds with.
+    pub(crate) fn features(&self, hwcap_names: &[&str], hwcap2_names: &[&str]) -> HashSet<String> {
+        let mut features = HashSet::new();
+        for (value, names) in [(self.hwcap, hwcap_names), (self.hwcap2, hwcap2_names)] {
+            for (bit, name) in names.iter().enumerate() {
+                if !name.is_empty() && value & (1 << bit) != 0 {
+                    features.insert(name.to_string());
+                }
+            }
+        }
+        features
+    }
+}
+
+/// Provides the hardware capabilities of the CPU on Linux.
+pub trait HwCapProvider {
+    /// Returns the hardware capabilities from the auxiliary vector. The bits are only meaningful
+    /// for the architecture `arch` they are requested for, e.g. `ppc64le`.
+    fn hwcaps(&self, arch: &str) -> std::io::Result<HwCaps>;
+
+    /// Reads the `MIDR_EL1` register. The kernel emulates this for userspace if the `cpuid`
+    /// hardware capability is set.
+    fn midr_el1(&self) -> std::io::Result<u64>;
+}
+
+/// Default implementation of the [`HwCapProvider`] trait that reads the auxiliary vector of the
+/// current process. It only describes the architecture the process was compiled for.
+#[derive(Default)]
+pub struct MachineHwCapProvider {}
+
+impl HwCapProvider for MachineHwCapProvider {
+    fn hwcaps(&self, arch: &str) -> std::io::Result<HwCaps> {
+        if normalize_architecture(arch) != target_architecture_compiler() {
+            return Err(std::io::Error::from(std::io::ErrorKind::Unsupported));
+        }
+
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                let hwcap = unsafe { libc::getauxval(libc::AT_HWCAP) };
+                let hwcap2 = unsafe { libc::getauxval(libc::AT_HWCAP2) };
+                if hwcap == 0 {
+                    return Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+                }
+                Ok(HwCaps {
+                    hwcap: hwcap as u64,
+                    hwcap2: hwcap2 as u64,
+                })
+            } else {
+                Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+            }
+        }
+    }
+
+    fn midr_el1(&self) -> std::io::Result<u64> {
+        cfg_if::cfg_if! {
+            if #[cfg(all(target_os = "linux", target_arch = "aarch64"))] {
+                let midr: u64;
+                unsafe {
+                    std::arch::asm!("mrs {}, MIDR_EL1", out(reg) midr, options(nomem, nostack));
+                }
+                Ok(midr)
+            } else {
+                Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+            }
+        }
+    }
+}
+
+/// The `HWCAP_CPUID` bit on AArch64, set if `MIDR_EL1` can be read from userspace.
+pub(crate) const AARCH64_HWCAP_CPUID: u64 = 1 << 11;
+
+/// Feature names of the `AT_HWCAP` bits on AArch64, as defined in
+/// `arch/arm64/include/uapi/asm/hwcap.h` of the Linux kernel.
+pub(crate) const AARCH64_HWCAP: &[&str] = &[
+    "fp", "asimd", "evtstrm", "aes", "pmull", "sha1", "sha2", "crc32", "atomics", "fphp",
+    "asimdhp", "cpuid", "asimdrdm", "jscvt", "fcma", "lrcpc", "dcpop", "sha3", "sm3", "sm4",
+    "asimddp", "sha512", "sve", "asimdfhm", "dit", "uscat", "ilrcpc", "flagm", "ssbs", "sb",
+    "paca", "pacg",
+];
+
+/// Feature names of the `AT_HWCAP2` bits on AArch64.
+pub(crate) const AARCH64_HWCAP2: &[&str] = &[
+    "dcpodp",
+    "sve2",
+    "sveaes",
+    "svepmull",
+    "svebitperm",
+    "svesha3",
+    "svesm4",
+    "flagm2",
+    "frint",
+    "svei8mm",
+    "svef32mm",
+    "svef64mm",
+    "svebf16",
+    "i8mm",
+    "bf16",
+    "dgh",
+    "rng",
+    "bti",
+    "mte",
+    "ecv",
+    "afp",
+    "rpres",
+    "mte3",
+    "sme",
+    "smei16i64",
+    "smef64f64",
+    "smei8i32",
+    "smef16f32",
+    "smeb16f32",
+    "smef32f32",
+    "smefa64",
+    "wfxt",
+    "ebf16",
+    "sveebf16",
+    "cssc",
+    "rprfm",
+    "sve2p1",
+];
+
+/// Feature names of the `AT_HWCAP` bits on POWER, as defined in
+/// `arch/powerpc/include/uapi/asm/cputable.h` of the Linux kernel.
+pub(crate) const PPC64_HWCAP: &[&str] = &[
+    "ppcle",
+    "true_le",
+    "",
+    "",
+    "",
+    "",
+    "archpmu",
+    "vsx",
+    "arch_2_06",
+    "power6x",
+    "dfp",
+    "pa6t",
+    "arch_2_05",
+    "ic_snoop",
+    "smt",
+    "booke",
+    "cellbe",
+    "power5+",
+    "power5",
+    "power4",
+    "notb",
+    "efpdouble",
+    "efpsingle",
+    "spe",
+    "ucache",
+    "4xxmac",
+    "mmu",
+    "fpu",
+    "altivec",
+    "ppc601",
+    "ppc64",
+    "ppc32",
+];
+
+/// Feature names of the `AT_HWCAP2` bits on POWER.
+pub(crate) const PPC64_HWCAP2: &[&str] = &[
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "mma",
+    "arch_3_1",
+    "htm-no-suspend",
+    "scv",
+    "darn",
+    "ieee128",
+    "arch_3_00",
+    "htm-nosc",
+    "vcrypto",
+    "tar",
+    "isel",
+    "ebb",
+    "dscr",
+    "htm",
+    "arch_2_07",
+];
@@ -1,10 +1,11 @@
-use crate::cpu::detect::target_architecture_uname;
-use crate::schema::{Compiler, CompilerSet};
+use super::database::{validate_definitions, TargetDatabase};
+use crate::schema::{Compiler, LoadError};
 use itertools::Itertools;
-use std::collections::{HashMap, HashSet};
-use std::fmt::{Debug, Formatter};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Debug, Display, Formatter};
 use std::iter;
-use std::sync::{Arc, OnceLock};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
 
 pub struct Microarchitecture {
     pub(crate) name: String,
@@ -113,10 +114,52 @@ impl Microarchitecture {
     }
 
     /// Returns all the known micro architectures.
+    ///
+    /// These are the targets of the default [`TargetDatabase`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the schemas cannot be loaded, see [`Self::try_known_targets`].
     pub fn known_targets() -> &'static HashMap<String, Arc<Microarchitecture>> {
-        static KNOWN_TARGETS: std::sync::OnceLock<HashMap<String, Arc<Microarchitecture>>> =
-            std::sync::OnceLock::new();
-        KNOWN_TARGETS.get_or_init(known_microarchitectures)
+        TargetDatabase::global().targets()
+    }
+
+    /// Returns all the known micro architectures, or the error that occurred while loading them.
+    pub fn try_known_targets() -> Result<&'static HashMap<String, Arc<Microarchitecture>>, LoadError>
+    {
+        TargetDatabase::try_global().map(|database| database.targets())
+    }
+
+    /// Adds custom micro architectures, e.g. site-local targets, to the known targets so that they
+    /// take part in detection and comparison.
+    ///
+    /// The parents of every micro architecture must be known or part of `definitions`, and they
+    /// must not derive from each other. The known targets are only constructed once, so this has
+    /// to be called before they are first used. Use [`TargetDatabase::extend`] to extend another
+    /// database.
+    pub fn extend_known_targets(
+        definitions: HashMap<String, crate::schema::Microarchitecture>,
+    ) -> Result<(), ExtendTargetsError> {
+        let mut custom = custom_targets();
+        if custom.loaded {
+            return Err(ExtendTargetsError::AlreadyLoaded);
+        }
+
+        // The same targets the default database is extended from, see `TargetDatabase::try_global`.
+        let base = TargetDatabase::try_base()?;
+        validate_definitions(&definitions, |name| {
+            base.get(name).is_some() || custom.definitions.contains_key(name)
+        })?;
+        custom.definitions.extend(definitions);
+        Ok(())
+    }
+
+    /// Returns the custom micro architectures that were added to the known targets. Afterwards no
+    /// more can be added.
+    pub(crate) fn take_custom_targets() -> HashMap<String, crate::schema::Microarchitecture> {
+        let mut custom = custom_targets();
+        custom.loaded = true;
+        std::mem::take(&mut custom.definitions)
     }
 
     /// Returns all the ancestors of this micro architecture.
@@ -197,78 +240,268 @@ impl Microarchitecture {
     }
 }
 
-#[derive(Debug)]
+/// Serializes the micro architecture like `to_dict` in the Python archspec: the name, vendor, own
+/// and all features, the names of the parents, the generation and the compiler flags.
+impl Serialize for Microarchitecture {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedMicroarchitecture {
+            name: self.name.clone(),
+            vendor: self.vendor.clone(),
+            features: self.features.iter().cloned().collect(),
+            all_features: self.all_features().into_iter().collect(),
+            parents: self
+                .parents
+                .iter()
+                .map(|parent| parent.name.clone())
+                .collect(),
+            generation: self.generation,
+            compilers: self
+                .compilers
+                .iter()
+                .map(|(name, compilers)| (name.clone(), compilers.clone()))
+                .collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Deserializes a micro architecture like `from_dict` in the Python archspec. The parents are
+/// looked up in the [known targets](Microarchitecture::known_targets), the features inherited from
+/// them are not read.
+impl<'de> Deserialize<'de> for Microarchitecture {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let data = SerializedMicroarchitecture::deserialize(deserializer)?;
+        let targets = Microarchitecture::try_known_targets().map_err(D::Error::custom)?;
+        let parents = data
+            .parents
+            .iter()
+            .map(|parent| {
+                targets.get(parent).cloned().ok_or_else(|| {
+                    D::Error::custom(format_args!(
+                        "unknown parent micro architecture '{}' of '{}'",
+                        parent, data.name
+                    ))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Microarchitecture::new_generation(
+            data.name,
+            parents,
+            data.vendor,
+            data.features.into_iter().collect(),
+            data.compilers.into_iter().collect(),
+            data.generation,
+        ))
+    }
+}
+
+/// The serialized form of a [`Microarchitecture`]. Sets and maps are ordered to keep the output
+/// stable.
+#[derive(Serialize, Deserialize)]
+struct SerializedMicroarchitecture {
+    name: String,
+    vendor: String,
+    features: BTreeSet<String>,
+    #[serde(default, skip_deserializing)]
+    all_features: BTreeSet<String>,
+    parents: Vec<String>,
+    #[serde(default)]
+    generation: usize,
+    #[serde(default)]
+    compilers: BTreeMap<String, Vec<Compiler>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct UnsupportedMicroarchitecture;
 
-fn known_microarchitectures() -> HashMap<String, Arc<Microarchitecture>> {
-    let mut known_targets: HashMap<String, Arc<Microarchitecture>> = HashMap::new();
-    let schema = crate::schema::MicroarchitecturesSchema::schema();
-
-    fn fill_target_from_map(
-        name: &str,
-        schema: &crate::schema::MicroarchitecturesSchema,
-        targets: &mut HashMap<String, Arc<Microarchitecture>>,
-    ) {
-        let data = &schema.microarchitectures;
-        let values = &data[name];
-        let parent_names = &values.from;
-        for parent in parent_names {
-            if !targets.contains_key(parent) {
-                fill_target_from_map(parent, schema, targets);
+/// Error returned when the micro architecture of the host cannot be detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectError {
+    /// No known micro architecture matches the host.
+    Unsupported,
+
+    /// The schema of the known targets could not be loaded.
+    Load(LoadError),
+}
+
+impl Display for DetectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DetectError::Unsupported => write!(f, "unsupported micro architecture"),
+            DetectError::Load(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for DetectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DetectError::Load(err) => Some(err),
+            DetectError::Unsupported => None,
+        }
+    }
+}
+
+impl From<UnsupportedMicroarchitecture> for DetectError {
+    fn from(_: UnsupportedMicroarchitecture) -> Self {
+        DetectError::Unsupported
+    }
+}
+
+impl From<LoadError> for DetectError {
+    fn from(err: LoadError) -> Self {
+        DetectError::Load(err)
+    }
+}
+
+/// Keeps `?` working in functions that return the error of earlier versions of [`super::host`].
+impl From<DetectError> for UnsupportedMicroarchitecture {
+    fn from(_: DetectError) -> Self {
+        UnsupportedMicroarchitecture
+    }
+}
+
+/// Error returned by [`Microarchitecture::extend_known_targets`] and [`TargetDatabase::extend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtendTargetsError {
+    /// A micro architecture with this name is already known.
+    AlreadyKnown(String),
+
+    /// A parent of a micro architecture is not known.
+    UnknownParent { target: String, parent: String },
+
+    /// The micro architectures derive from each other.
+    Cycle(Vec<String>),
+
+    /// The known targets were already constructed.
+    AlreadyLoaded,
+
+    /// The schema of the known targets could not be loaded.
+    Load(LoadError),
+}
+
+impl Display for ExtendTargetsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtendTargetsError::AlreadyKnown(name) => {
+                write!(f, "the micro architecture '{}' is already known", name)
+            }
+            ExtendTargetsError::UnknownParent { target, parent } => write!(
+                f,
+                "the parent '{}' of the micro architecture '{}' is not known",
+                parent, target
+            ),
+            ExtendTargetsError::Cycle(names) => write!(
+                f,
+                "the micro architectures derive from each other: {}",
+                names.join(" -> ")
+            ),
+            ExtendTargetsError::AlreadyLoaded => {
+                write!(f, "the known targets were already constructed")
             }
+            ExtendTargetsError::Load(err) => write!(f, "{}", err),
         }
-        let parents = parent_names
-            .iter()
-            .map(|parent| targets[parent].clone())
-            .collect::<Vec<Arc<Microarchitecture>>>();
-
-        let vendor = values.vendor.clone();
-        let features: HashSet<String> = values.features.iter().cloned().collect();
-        let compilers: HashMap<String, Vec<Compiler>> = values
-            .compilers
-            .as_ref()
-            .map(|compilers| {
-                compilers
-                    .iter()
-                    .map(|(vendor, set)| {
-                        (
-                            vendor.clone(),
-                            // normalize to a sequence of compiler definitions
-                            match set {
-                                CompilerSet::Several(cs) => cs.clone(),
-                                CompilerSet::Single(c) => vec![c.clone()],
-                            },
-                        )
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
-        let generation = values.generation.unwrap_or(0);
+    }
+}
 
-        targets.insert(
-            name.to_string(),
-            Arc::new(Microarchitecture::new_generation(
-                name.to_string(),
-                parents,
-                vendor,
-                features,
-                compilers,
-                generation,
-            )),
+impl std::error::Error for ExtendTargetsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExtendTargetsError::Load(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<LoadError> for ExtendTargetsError {
+    fn from(err: LoadError) -> Self {
+        ExtendTargetsError::Load(err)
+    }
+}
+
+/// Custom micro architectures that are added to the known targets when they are constructed.
+#[derive(Default)]
+struct CustomTargets {
+    definitions: HashMap<String, crate::schema::Microarchitecture>,
+    loaded: bool,
+}
+
+fn custom_targets() -> MutexGuard<'static, CustomTargets> {
+    static CUSTOM_TARGETS: OnceLock<Mutex<CustomTargets>> = OnceLock::new();
+    CUSTOM_TARGETS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DetectError, ExtendTargetsError, Microarchitecture, UnsupportedMicroarchitecture};
+    use std::collections::HashMap;
+
+    fn extend(json: &str) -> Result<(), ExtendTargetsError> {
+        Microarchitecture::extend_known_targets(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        // nehalem inherits `lahf_lm` from x86_64_v2 without listing it.
+        let nehalem = &Microarchitecture::known_targets()["nehalem"];
+        let json = serde_json::to_value(nehalem.as_ref()).unwrap();
+        assert_eq!(json["name"], "nehalem");
+        assert_eq!(json["parents"], serde_json::json!(["core2", "x86_64_v2"]));
+        assert!(json["all_features"]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("lahf_lm")));
+        assert!(!json["features"]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("lahf_lm")));
+
+        let deserialized: Microarchitecture = serde_json::from_value(json).unwrap();
+        assert_eq!(&deserialized, nehalem.as_ref());
+        assert_eq!(deserialized.ancestors(), nehalem.ancestors());
+
+        let err = serde_json::from_str::<Microarchitecture>(
+            r#"{ "name": "ourcloud_v1", "vendor": "generic", "features": [], "parents": ["ourcloud_v0"] }"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown parent micro architecture 'ourcloud_v0' of 'ourcloud_v1'"
         );
+
+        let deserialized: Microarchitecture = serde_json::from_str(
+            r#"{ "name": "ourcloud_v1", "vendor": "generic", "features": ["avx512f"], "parents": ["x86_64_v3"] }"#,
+        )
+        .unwrap();
+        assert_eq!(deserialized.generation(), 0);
+        assert_eq!(deserialized.compilers, HashMap::new());
+        assert!(deserialized.all_features().contains("avx2"));
     }
 
-    for name in schema.microarchitectures.keys() {
-        if !known_targets.contains_key(name) {
-            fill_target_from_map(name, schema, &mut known_targets);
+    #[test]
+    fn test_unsupported_from_detect_error() {
+        fn host() -> Result<(), UnsupportedMicroarchitecture> {
+            Err(DetectError::Unsupported)?;
+            Ok(())
         }
+        assert_eq!(host(), Err(UnsupportedMicroarchitecture));
     }
 
-    if let Ok(host_platform) = target_architecture_uname() {
-        known_targets
-            .entry(host_platform.to_string())
-            .or_insert_with(|| Arc::new(Microarchitecture::generic(&host_platform)));
+    #[test]
+    fn test_extend_known_targets_after_load() {
+        // Once the known targets are constructed, that is reported before any other error.
+        Microarchitecture::known_targets();
+        assert_eq!(
+            extend(
+                r#"{ "icelake": { "from": "x86_64_v3", "vendor": "GenuineIntel", "features": [] } }"#
+            ),
+            Err(ExtendTargetsError::AlreadyLoaded)
+        );
     }
-
-    known_targets
 }
@@ -1,5 +1,22 @@
+mod database;
 mod detect;
+mod emulation;
+mod hwcap;
 mod microarchitecture;
+mod snapshot;
+mod target_name;
 
-pub use detect::host;
-pub use microarchitecture::{Microarchitecture, UnsupportedMicroarchitecture};
+pub use crate::cpuid::{CpuIdProvider, CpuIdRegisters, MachineCpuIdProvider};
+pub use database::TargetDatabase;
+pub use detect::{
+    host, host_info, host_info_or_generic, host_info_uncached, host_uncached, Fallback, HostInfo,
+    MachineSysCtlProvider, ProcCpuInfo, SysCtlProvider, TargetDetector, CHECK_HOST_TARGET_ENV,
+    HOST_TARGET_ENV,
+};
+pub use emulation::{host_emulation, Emulation, EmulationInfo};
+pub use hwcap::{HwCapProvider, HwCaps, MachineHwCapProvider};
+pub use microarchitecture::{
+    DetectError, ExtendTargetsError, Microarchitecture, UnsupportedMicroarchitecture,
+};
+pub use snapshot::{CpuIdLeaf, ProbeSnapshot};
+pub use target_name::{TargetName, TargetNameError};
@@ -0,0 +1,241 @@
+//! Snapshots of everything that is read from the system to detect the host, so that a detection
+//! can be reproduced on another machine.
+
+use super::detect::{
+    read_binfmt_interpreters, target_architecture_compiler, MachineSysCtlProvider, ProcCpuInfo,
+    SysCtlProvider, TargetDetector,
+};
+use super::hwcap::{HwCapProvider, HwCaps, MachineHwCapProvider};
+use crate::cpuid::{CpuIdProvider, CpuIdRegisters, MachineCpuIdProvider};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+/// Everything that was read from the system while detecting the host.
+///
+/// A snapshot is captured with [`ProbeSnapshot::capture`] and can be serialized, e.g. to attach it
+/// to a bug report. [`ProbeSnapshot::detector`] replays the detection against it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeSnapshot {
+    /// The operating system, as in [`std::env::consts::OS`].
+    pub os: String,
+
+    /// The architecture of the machine, e.g. as reported by `uname` on Linux.
+    pub arch: String,
+
+    /// The contents of `/proc/cpuinfo`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proc_cpu_info: Option<String>,
+
+    /// The `CPUID` leaves that were queried.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cpuid: Vec<CpuIdLeaf>,
+
+    /// The value of the `XCR0` register.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xcr0: Option<u64>,
+
+    /// The `sysctl` entries that were queried and exist.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sysctl: BTreeMap<String, String>,
+
+    /// The hardware capabilities from the auxiliary vector.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hwcaps: Option<HwCaps>,
+
+    /// The value of the `MIDR_EL1` register.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub midr_el1: Option<u64>,
+
+    /// The enabled interpreters in `/proc/sys/fs/binfmt_misc`, used to detect emulation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub binfmt_interpreters: Vec<String>,
+}
+
+/// The result of the `CPUID` instruction for a leaf and sub-leaf.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuIdLeaf {
+    /// The leaf, passed in EAX.
+    pub leaf: u32,
+
+    /// The sub-leaf, passed in ECX.
+    pub sub_leaf: u32,
+
+    /// The returned registers.
+    #[serde(flatten)]
+    pub registers: CpuIdRegisters,
+}
+
+impl ProbeSnapshot {
+    /// Probes the machine this is running on like [`super::host`] and
+    /// [`super::host_emulation`] do, and records every value that is read.
+    pub fn capture() -> Self {
+        let os = std::env::consts::OS;
+
+        let (proc_cpu_info, binfmt_interpreters) = if os == "linux" {
+            let mut interpreters = read_binfmt_interpreters();
+            interpreters.sort();
+            (std::fs::read_to_string("/proc/cpuinfo").ok(), interpreters)
+        } else {
+            (None, Vec::new())
+        };
+
+        let snapshot = Rc::new(RefCell::new(Self {
+            os: os.to_string(),
+            proc_cpu_info,
+            binfmt_interpreters,
+            ..Self::default()
+        }));
+
+        let recording = || {
+            TargetDetector::new()
+                .with_target_os(os)
+                .with_sysctl_provider(Recorder {
+                    inner: MachineSysCtlProvider::default(),
+                    snapshot: snapshot.clone(),
+                })
+                .with_cpuid_provider(Recorder {
+                    inner: MachineCpuIdProvider::default(),
+                    snapshot: snapshot.clone(),
+                })
+                .with_hwcap_provider(Recorder {
+                    inner: MachineHwCapProvider::default(),
+                    snapshot: snapshot.clone(),
+                })
+        };
+
+        let arch = recording()
+            .machine_architecture(os)
+            .unwrap_or_else(|_| target_architecture_compiler().to_string());
+        snapshot.borrow_mut().arch = arch;
+
+        let detector = snapshot.borrow().with_system(recording());
+        detector.detect_info_or_generic();
+        let detector = snapshot.borrow().with_system(recording());
+        let _ = detector.detect_emulation();
+
+        let snapshot = snapshot.borrow().clone();
+        snapshot
+    }
+
+    /// Returns a detector that reads from this snapshot instead of the machine.
+    pub fn detector(&self) -> TargetDetector<ProbeSnapshot, ProbeSnapshot, ProbeSnapshot> {
+        self.with_system(TargetDetector::new())
+            .with_sysctl_provider(self.clone())
+            .with_cpuid_provider(self.clone())
+            .with_hwcap_provider(self.clone())
+    }
+
+    /// Configures the operating system, architecture, `/proc/cpuinfo` and `binfmt_misc`
+    /// interpreters of the detector.
+    fn with_system<S, C, H>(&self, detector: TargetDetector<S, C, H>) -> TargetDetector<S, C, H>
+    where
+        S: SysCtlProvider,
+        C: CpuIdProvider,
+        H: HwCapProvider,
+    {
+        let detector = detector
+            .with_target_os(&self.os)
+            .with_target_arch(&self.arch)
+            .with_binfmt_interpreters(self.binfmt_interpreters.clone());
+        match &self.proc_cpu_info {
+            Some(contents) => detector.with_proc_cpu_info(ProcCpuInfo::parse(contents)),
+            None => detector.with_proc_cpu_info(ProcCpuInfo::default()),
+        }
+    }
+}
+
+impl CpuIdProvider for ProbeSnapshot {
+    fn cpuid(&self, leaf: u32, sub_leaf: u32) -> CpuIdRegisters {
+        self.cpuid
+            .iter()
+            .find(|entry| entry.leaf == leaf && entry.sub_leaf == sub_leaf)
+            .map(|entry| entry.registers.clone())
+            .unwrap_or_default()
+    }
+
+    fn xgetbv(&self, _xcr: u32) -> u64 {
+        self.xcr0.unwrap_or_default()
+    }
+}
+
+impl SysCtlProvider for ProbeSnapshot {
+    fn sysctl(&self, name: &str) -> std::io::Result<String> {
+        self.sysctl
+            .get(name)
+            .cloned()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+    }
+}
+
+impl HwCapProvider for ProbeSnapshot {
+    fn hwcaps(&self, _arch: &str) -> std::io::Result<HwCaps> {
+        self.hwcaps
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+    }
+
+    fn midr_el1(&self) -> std::io::Result<u64> {
+        self.midr_el1
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+    }
+}
+
+/// Forwards to another provider and records the results in a snapshot. Only the values that
+/// detection asks for are read, e.g. `MIDR_EL1` must not be read if the kernel does not emulate it.
+struct Recorder<P> {
+    inner: P,
+    snapshot: Rc<RefCell<ProbeSnapshot>>,
+}
+
+impl<P: CpuIdProvider> CpuIdProvider for Recorder<P> {
+    fn cpuid(&self, leaf: u32, sub_leaf: u32) -> CpuIdRegisters {
+        let registers = self.inner.cpuid(leaf, sub_leaf);
+        let mut snapshot = self.snapshot.borrow_mut();
+        if !snapshot
+            .cpuid
+            .iter()
+            .any(|entry| entry.leaf == leaf && entry.sub_leaf == sub_leaf)
+        {
+            snapshot.cpuid.push(CpuIdLeaf {
+                leaf,
+                sub_leaf,
+                registers: registers.clone(),
+            });
+        }
+        registers
+    }
+
+    fn xgetbv(&self, xcr: u32) -> u64 {
+        let value = self.inner.xgetbv(xcr);
+        if xcr == 0 {
+            self.snapshot.borrow_mut().xcr0 = Some(value);
+        }
+        value
+    }
+}
+
+impl<P: SysCtlProvider> SysCtlProvider for Recorder<P> {
+    fn sysctl(&self, name: &str) -> std::io::Result<String> {
+        let value = self.inner.sysctl(name)?;
+        self.snapshot
+            .borrow_mut()
+            .sysctl
+            .insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+}
+
+impl<P: HwCapProvider> HwCapProvider for Recorder<P> {
+    fn hwcaps(&self, arch: &str) -> std::io::Result<HwCaps> {
+        let hwcaps = self.inner.hwcaps(arch)?;
+        self.snapshot.borrow_mut().hwcaps = Some(hwcaps);
+        Ok(hwcaps)
+    }
+
+    fn midr_el1(&self) -> std::io::Result<u64> {
+        let midr = self.inner.midr_el1()?;
+        self.snapshot.borrow_mut().midr_el1 = Some(midr);
+        Ok(midr)
+    }
+}
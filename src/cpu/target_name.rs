@@ -0,0 +1,131 @@
+use super::Microarchitecture;
+use crate::schema::LoadError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// A [known](Microarchitecture::known_targets) micro architecture that is serialized as its name,
+/// e.g. to refer to a target in a configuration file or lockfile.
+///
+/// Deserializing a name that is not a known target fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetName(Arc<Microarchitecture>);
+
+impl TargetName {
+    /// Returns the micro architecture.
+    pub fn target(&self) -> &Arc<Microarchitecture> {
+        &self.0
+    }
+
+    /// Returns the micro architecture.
+    pub fn into_inner(self) -> Arc<Microarchitecture> {
+        self.0
+    }
+}
+
+impl From<Arc<Microarchitecture>> for TargetName {
+    fn from(target: Arc<Microarchitecture>) -> Self {
+        Self(target)
+    }
+}
+
+impl From<TargetName> for Arc<Microarchitecture> {
+    fn from(name: TargetName) -> Self {
+        name.0
+    }
+}
+
+impl Deref for TargetName {
+    type Target = Microarchitecture;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Display for TargetName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.name())
+    }
+}
+
+impl FromStr for TargetName {
+    type Err = TargetNameError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Microarchitecture::try_known_targets()
+            .map_err(TargetNameError::Load)?
+            .get(name)
+            .cloned()
+            .map(TargetName)
+            .ok_or_else(|| TargetNameError::Unknown(name.to_string()))
+    }
+}
+
+impl Serialize for TargetName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for TargetName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Error returned when a [`TargetName`] cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetNameError {
+    /// The name is not a known micro architecture.
+    Unknown(String),
+
+    /// The known micro architectures could not be loaded.
+    Load(LoadError),
+}
+
+impl Display for TargetNameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetNameError::Unknown(name) => write!(f, "unknown micro architecture '{}'", name),
+            TargetNameError::Load(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for TargetNameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TargetNameError::Load(err) => Some(err),
+            TargetNameError::Unknown(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TargetName;
+    use crate::cpu::Microarchitecture;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_target_name() {
+        let targets: BTreeMap<String, TargetName> =
+            serde_json::from_str(r#"{ "default": "x86_64_v3", "fast": "zen4" }"#).unwrap();
+        assert_eq!(
+            targets["fast"].target(),
+            &Microarchitecture::known_targets()["zen4"]
+        );
+        assert_eq!(targets["default"].vendor(), "generic");
+        assert_eq!(
+            serde_json::to_string(&targets).unwrap(),
+            r#"{"default":"x86_64_v3","fast":"zen4"}"#
+        );
+
+        let err = serde_json::from_str::<TargetName>(r#""zen9""#).unwrap_err();
+        assert_eq!(err.to_string(), "unknown micro architecture 'zen9'");
+    }
+}
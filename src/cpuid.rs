@@ -2,15 +2,37 @@
 
 use crate::schema::{CpuIdSchema, CpuRegister};
 use itertools::chain;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::ffi::CStr;
 
-pub(crate) trait CpuIdProvider {
+/// Provides the results of the `CPUID` instruction on x86 and x86_64.
+pub trait CpuIdProvider {
+    /// Executes `CPUID` for the given leaf (EAX) and sub-leaf (ECX).
     fn cpuid(&self, leaf: u32, sub_leaf: u32) -> CpuIdRegisters;
+
+    /// Reads the extended control register `xcr` with the `XGETBV` instruction.
+    ///
+    /// This is only called if CPUID reports that the OS has enabled `XSAVE` (OSXSAVE).
+    fn xgetbv(&self, xcr: u32) -> u64;
 }
 
-#[derive(Debug, Clone)]
-pub(crate) struct CpuIdRegisters {
+/// Bits of the XCR0 register that indicate which register states the OS saves and restores on a
+/// context switch. Instructions that use a register state which is not enabled fault.
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+const XCR0_OPMASK: u64 = 1 << 5;
+const XCR0_ZMM_HI256: u64 = 1 << 6;
+const XCR0_HI16_ZMM: u64 = 1 << 7;
+const XCR0_TILECFG: u64 = 1 << 17;
+const XCR0_TILEDATA: u64 = 1 << 18;
+
+/// Features besides the `avx*` family that require the OS to save the YMM register state.
+const YMM_FEATURES: &[&str] = &["fma", "fma4", "f16c", "xop", "vaes", "vpclmulqdq"];
+
+/// The registers returned by the `CPUID` instruction.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuIdRegisters {
     /// EAX register.
     pub eax: u32,
     /// EBX register.
@@ -45,22 +67,48 @@ impl From<std::arch::x86::CpuidResult> for CpuIdRegisters {
     }
 }
 
-/// Default implementation of the `CpuidProvider` trait. This implementation uses the
-/// [`__cpuid_count`] intrinsic to read actual CPUID information.
+/// Default implementation of the [`CpuIdProvider`] trait. This implementation uses the
+/// `__cpuid_count` intrinsic to read actual CPUID information.
 ///
-/// This implementation is only available on x86 and x86_64 architectures.
+/// The instruction is only available on x86 and x86_64, on other architectures every register
+/// reads as zero, like for a leaf that is not supported.
 #[derive(Default)]
-pub(crate) struct MachineCpuIdProvider {}
+pub struct MachineCpuIdProvider {}
 
 impl CpuIdProvider for MachineCpuIdProvider {
     fn cpuid(&self, leaf: u32, sub_leaf: u32) -> CpuIdRegisters {
         cfg_if::cfg_if! {
             if #[cfg(target_arch = "x86_64")] {
+                // `__cpuid_count` is safe on newer toolchains but unsafe on older ones.
+                #[allow(unused_unsafe)]
                 unsafe { std::arch::x86_64::__cpuid_count(leaf, sub_leaf).into() }
             } else if #[cfg(target_arch = "x86")] {
+                #[allow(unused_unsafe)]
                 unsafe { std::arch::x86::__cpuid_count(leaf, sub_leaf).into() }
             } else {
-                unimplemented!("Unsupported architecture for CPUID instruction ({leaf} {sub_leaf})")
+                let _ = (leaf, sub_leaf);
+                CpuIdRegisters::default()
+            }
+        }
+    }
+
+    fn xgetbv(&self, xcr: u32) -> u64 {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_arch = "x86_64", target_arch = "x86"))] {
+                let (eax, edx): (u32, u32);
+                unsafe {
+                    std::arch::asm!(
+                        "xgetbv",
+                        in("ecx") xcr,
+                        out("eax") eax,
+                        out("edx") edx,
+                        options(nomem, nostack, preserves_flags)
+                    );
+                }
+                (u64::from(edx) << 32) | u64::from(eax)
+            } else {
+                let _ = xcr;
+                0
             }
         }
     }
@@ -79,9 +127,7 @@ pub(crate) struct CpuId {
 }
 
 impl CpuId {
-    pub fn detect<P: CpuIdProvider>(provider: &P) -> Self {
-        let schema = CpuIdSchema::schema();
-
+    pub fn detect<P: CpuIdProvider>(provider: &P, schema: &CpuIdSchema) -> Self {
         // Read the vendor information
         let registers = provider.cpuid(schema.vendor.input.eax, schema.vendor.input.ecx);
         let highest_basic_support = registers.eax;
@@ -125,37 +171,11 @@ impl CpuId {
             }
         }
 
-        // Read brand name if supported.
-        let brand = if highest_extension_support >= 0x80000004 {
-            let registers = (
-                provider.cpuid(0x80000002, 0),
-                provider.cpuid(0x80000003, 0),
-                provider.cpuid(0x80000004, 0),
-            );
-
-            let vendor_bytes: Vec<_> = chain!(
-                registers.0.eax.to_le_bytes(),
-                registers.0.ebx.to_le_bytes(),
-                registers.0.ecx.to_le_bytes(),
-                registers.0.edx.to_le_bytes(),
-                registers.1.eax.to_le_bytes(),
-                registers.1.ebx.to_le_bytes(),
-                registers.1.ecx.to_le_bytes(),
-                registers.1.edx.to_le_bytes(),
-                registers.2.eax.to_le_bytes(),
-                registers.2.ebx.to_le_bytes(),
-                registers.2.ecx.to_le_bytes(),
-                registers.2.edx.to_le_bytes(),
-            )
-            .collect();
-            let brand_string = match CStr::from_bytes_until_nul(&vendor_bytes) {
-                Ok(cstr) => cstr.to_string_lossy(),
-                Err(_) => String::from_utf8_lossy(&vendor_bytes),
-            };
-            Some(brand_string.trim().to_string())
-        } else {
-            None
-        };
+        // CPUID only reports what the processor supports, the OS also has to enable saving the
+        // corresponding register state. Drop the features that would fault if used.
+        remove_features_disabled_by_os(provider, &mut features);
+
+        let brand = read_brand(provider, highest_extension_support);
 
         Self {
             vendor,
@@ -163,4 +183,92 @@ impl CpuId {
             brand,
         }
     }
+
+    /// Reads the brand string of the CPU, if supported.
+    pub fn brand<P: CpuIdProvider>(provider: &P, schema: &CpuIdSchema) -> Option<String> {
+        let registers = provider.cpuid(
+            schema.highest_extension_support.input.eax,
+            schema.highest_extension_support.input.ecx,
+        );
+        read_brand(provider, registers.eax)
+    }
+
+    /// Returns the vendor signature of the hypervisor if the CPU reports that it runs under one,
+    /// e.g. `KVMKVMKVM` or `Microsoft Hv`. The signature is empty if the hypervisor has none.
+    pub fn hypervisor<P: CpuIdProvider>(provider: &P) -> Option<String> {
+        // The hypervisor bit is reserved on physical CPUs and always set by hypervisors.
+        if provider.cpuid(1, 0).ecx & (1 << 31) == 0 {
+            return None;
+        }
+
+        // Hypervisors report their vendor in the first leaf of the range reserved for them.
+        let registers = provider.cpuid(0x40000000, 0);
+        let vendor_bytes: Vec<_> = chain!(
+            registers.ebx.to_le_bytes(),
+            registers.ecx.to_le_bytes(),
+            registers.edx.to_le_bytes()
+        )
+        .collect();
+        Some(
+            String::from_utf8_lossy(&vendor_bytes)
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string(),
+        )
+    }
+}
+
+/// Reads the brand string from the extended leaves if `highest_extension_support` includes them.
+fn read_brand<P: CpuIdProvider>(provider: &P, highest_extension_support: u32) -> Option<String> {
+    if highest_extension_support < 0x80000004 {
+        return None;
+    }
+
+    let registers = (
+        provider.cpuid(0x80000002, 0),
+        provider.cpuid(0x80000003, 0),
+        provider.cpuid(0x80000004, 0),
+    );
+
+    let vendor_bytes: Vec<_> = chain!(
+        registers.0.eax.to_le_bytes(),
+        registers.0.ebx.to_le_bytes(),
+        registers.0.ecx.to_le_bytes(),
+        registers.0.edx.to_le_bytes(),
+        registers.1.eax.to_le_bytes(),
+        registers.1.ebx.to_le_bytes(),
+        registers.1.ecx.to_le_bytes(),
+        registers.1.edx.to_le_bytes(),
+        registers.2.eax.to_le_bytes(),
+        registers.2.ebx.to_le_bytes(),
+        registers.2.ecx.to_le_bytes(),
+        registers.2.edx.to_le_bytes(),
+    )
+    .collect();
+    let brand_string = match CStr::from_bytes_until_nul(&vendor_bytes) {
+        Ok(cstr) => cstr.to_string_lossy(),
+        Err(_) => String::from_utf8_lossy(&vendor_bytes),
+    };
+    Some(brand_string.trim().to_string())
+}
+
+/// Removes the features from `features` whose register state is not enabled by the OS in XCR0.
+fn remove_features_disabled_by_os<P: CpuIdProvider>(provider: &P, features: &mut HashSet<String>) {
+    let xcr0 = if features.contains("osxsave") {
+        provider.xgetbv(0)
+    } else {
+        0
+    };
+
+    if xcr0 & (XCR0_SSE | XCR0_AVX) != XCR0_SSE | XCR0_AVX {
+        features.retain(|f| !f.starts_with("avx") && !YMM_FEATURES.contains(&f.as_str()));
+    }
+
+    let avx512_state = XCR0_OPMASK | XCR0_ZMM_HI256 | XCR0_HI16_ZMM;
+    if xcr0 & avx512_state != avx512_state {
+        features.retain(|f| !f.starts_with("avx512"));
+    }
+
+    if xcr0 & (XCR0_TILECFG | XCR0_TILEDATA) != XCR0_TILECFG | XCR0_TILEDATA {
+        features.retain(|f| !f.starts_with("amx"));
+    }
 }
@@ -1,7 +1,9 @@
+use super::LoadError;
 use serde::Deserialize;
-use std::sync::OnceLock;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Deserialize)]
 pub struct CpuIdSchema {
     pub vendor: CpuIdProperty,
     pub highest_extension_support: CpuIdProperty,
@@ -11,32 +13,64 @@ pub struct CpuIdSchema {
 }
 
 impl CpuIdSchema {
+    /// Returns the schema of the embedded `cpuid.json`, or the one in [`super::cpu_dir`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file cannot be loaded, see [`Self::try_load`].
     pub fn schema() -> &'static CpuIdSchema {
-        static SCHEMA: OnceLock<CpuIdSchema> = OnceLock::new();
-        SCHEMA.get_or_init(|| {
-            serde_json::from_str(include_str!(concat!(
-                env!("CARGO_MANIFEST_DIR"),
-                "/json/cpu/cpuid.json"
-            )))
-            .expect("Failed to load cpuid.json")
-        })
+        Self::try_load().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Returns the schema of the embedded `cpuid.json`, or the one in [`super::cpu_dir`]. The file
+    /// is only loaded once.
+    pub fn try_load() -> Result<&'static CpuIdSchema, LoadError> {
+        Self::try_shared().map(|schema| schema.as_ref())
+    }
+
+    /// Returns the schema shared by [`Self::try_load`] and the default target database.
+    pub(crate) fn try_shared() -> Result<&'static Arc<CpuIdSchema>, LoadError> {
+        static SCHEMA: OnceLock<Result<Arc<CpuIdSchema>, LoadError>> = OnceLock::new();
+        SCHEMA
+            .get_or_init(|| {
+                let schema = match super::cpu_dir() {
+                    Some(dir) => {
+                        let file = dir.join("cpuid.json");
+                        Self::parse(&file, &super::read_file(&file)?)?
+                    }
+                    None => super::embedded::cpuid(),
+                };
+                Ok(Arc::new(schema))
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Parses the contents of a `cpuid.json` file.
+    pub fn from_json(json: &str) -> Result<Self, LoadError> {
+        Self::parse(Path::new("cpuid.json"), json)
+    }
+
+    /// Parses the contents of the given `cpuid.json` file, like [`Self::from_json`].
+    pub(crate) fn parse(file: &Path, json: &str) -> Result<Self, LoadError> {
+        super::parse_json(file, json)
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Deserialize)]
 pub struct CpuIdProperty {
     pub description: String,
     pub input: CpuIdInput,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Deserialize)]
 pub struct CpuIdFlags {
     pub description: String,
     pub input: CpuIdInput,
     pub bits: Vec<CpuIdBits>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Deserialize)]
 pub struct CpuIdBits {
     pub name: String,
     pub register: CpuRegister,
@@ -52,7 +86,7 @@ pub enum CpuRegister {
     Edx,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Deserialize)]
 pub struct CpuIdInput {
     pub eax: u32,
     pub ecx: u32,
@@ -0,0 +1,210 @@
+//! The embedded schemas as static tables, generated by the build script from the archspec-json
+//! files. Constructing the schemas from these is much cheaper than parsing the JSON at runtime.
+//!
+//! Only the schemas are generated, the graph of [`crate::cpu::Microarchitecture`]s is still linked
+//! by [`crate::cpu::TargetDatabase::new`] the first time the default database is used.
+
+use super::{
+    Compiler, CompilerSet, Conversions, CpuIdBits, CpuIdFlags, CpuIdInput, CpuIdProperty,
+    CpuIdSchema, CpuRegister, FeatureAlias, JsonVersion, Microarchitecture,
+    MicroarchitecturesSchema,
+};
+use std::collections::HashMap;
+
+struct EmbeddedMicroarchitecture {
+    name: &'static str,
+    from: &'static [&'static str],
+    vendor: &'static str,
+    features: &'static [&'static str],
+    compilers: Option<&'static [(&'static str, EmbeddedCompilerSet)]>,
+    generation: Option<usize>,
+}
+
+enum EmbeddedCompilerSet {
+    Several(&'static [EmbeddedCompiler]),
+    #[allow(dead_code)]
+    Single(EmbeddedCompiler),
+}
+
+struct EmbeddedCompiler {
+    versions: &'static str,
+    flags: &'static str,
+    name: Option<&'static str>,
+}
+
+struct EmbeddedFeatureAlias {
+    name: &'static str,
+    reason: Option<&'static str>,
+    any_of: Option<&'static [&'static str]>,
+    families: Option<&'static [&'static str]>,
+}
+
+struct EmbeddedCpuIdProperty {
+    description: &'static str,
+    eax: u32,
+    ecx: u32,
+}
+
+struct EmbeddedCpuIdFlags {
+    property: EmbeddedCpuIdProperty,
+    bits: &'static [(&'static str, CpuRegister, u8)],
+}
+
+include!(concat!(env!("OUT_DIR"), "/embedded.rs"));
+
+/// Returns the schema of the embedded `microarchitectures.json`, including the microarchitectures
+/// that this crate maintains.
+pub(super) fn microarchitectures() -> MicroarchitecturesSchema {
+    MicroarchitecturesSchema {
+        microarchitectures: MICROARCHITECTURES
+            .iter()
+            .map(|target| (target.name.to_string(), target.to_schema()))
+            .collect(),
+        feature_aliases: FEATURE_ALIASES
+            .iter()
+            .map(|alias| (alias.name.to_string(), alias.to_schema()))
+            .collect(),
+        conversions: Conversions {
+            description: CONVERSIONS_DESCRIPTION.to_string(),
+            arm_vendors: to_map(ARM_VENDORS),
+            darwin_flags: to_map(DARWIN_FLAGS),
+        },
+    }
+}
+
+/// Returns the version of the embedded files.
+pub(super) fn version() -> JsonVersion {
+    JsonVersion {
+        commit: JSON_COMMIT,
+        checksum: JSON_CHECKSUM,
+    }
+}
+
+/// Returns the schema of the embedded `cpuid.json`.
+pub(super) fn cpuid() -> CpuIdSchema {
+    CpuIdSchema {
+        vendor: CPUID_VENDOR.to_schema(),
+        highest_extension_support: CPUID_HIGHEST_EXTENSION_SUPPORT.to_schema(),
+        flags: CPUID_FLAGS
+            .iter()
+            .map(EmbeddedCpuIdFlags::to_schema)
+            .collect(),
+        extension_flags: CPUID_EXTENSION_FLAGS
+            .iter()
+            .map(EmbeddedCpuIdFlags::to_schema)
+            .collect(),
+    }
+}
+
+impl EmbeddedMicroarchitecture {
+    fn to_schema(&self) -> Microarchitecture {
+        Microarchitecture {
+            from: to_strings(self.from),
+            vendor: self.vendor.to_string(),
+            features: to_strings(self.features),
+            compilers: self.compilers.map(|compilers| {
+                compilers
+                    .iter()
+                    .map(|(compiler, set)| (compiler.to_string(), set.to_schema()))
+                    .collect()
+            }),
+            generation: self.generation,
+        }
+    }
+}
+
+impl EmbeddedCompilerSet {
+    fn to_schema(&self) -> CompilerSet {
+        match self {
+            EmbeddedCompilerSet::Several(compilers) => {
+                CompilerSet::Several(compilers.iter().map(EmbeddedCompiler::to_schema).collect())
+            }
+            EmbeddedCompilerSet::Single(compiler) => CompilerSet::Single(compiler.to_schema()),
+        }
+    }
+}
+
+impl EmbeddedCompiler {
+    fn to_schema(&self) -> Compiler {
+        Compiler {
+            versions: self.versions.to_string(),
+            flags: self.flags.to_string(),
+            name: self.name.map(str::to_string),
+        }
+    }
+}
+
+impl EmbeddedFeatureAlias {
+    fn to_schema(&self) -> FeatureAlias {
+        FeatureAlias {
+            reason: self.reason.map(str::to_string),
+            any_of: self.any_of.map(to_strings),
+            families: self.families.map(to_strings),
+        }
+    }
+}
+
+impl EmbeddedCpuIdProperty {
+    fn to_schema(&self) -> CpuIdProperty {
+        CpuIdProperty {
+            description: self.description.to_string(),
+            input: CpuIdInput {
+                eax: self.eax,
+                ecx: self.ecx,
+            },
+        }
+    }
+}
+
+impl EmbeddedCpuIdFlags {
+    fn to_schema(&self) -> CpuIdFlags {
+        let property = self.property.to_schema();
+        CpuIdFlags {
+            description: property.description,
+            input: property.input,
+            bits: self
+                .bits
+                .iter()
+                .map(|&(name, register, bit)| CpuIdBits {
+                    name: name.to_string(),
+                    register,
+                    bit,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn to_strings(strings: &[&str]) -> Vec<String> {
+    strings.iter().map(|s| s.to_string()).collect()
+}
+
+fn to_map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::{CpuIdSchema, MicroarchitecturesSchema};
+
+    #[test]
+    fn embedded_matches_json() {
+        let microarchitectures = MicroarchitecturesSchema::from_json(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/json/cpu/microarchitectures.json"
+        )))
+        .and_then(MicroarchitecturesSchema::with_extra_microarchitectures)
+        .unwrap();
+        assert_eq!(super::microarchitectures(), microarchitectures);
+
+        let cpuid = CpuIdSchema::from_json(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/json/cpu/cpuid.json"
+        )))
+        .unwrap();
+        assert_eq!(super::cpuid(), cpuid);
+    }
+}
@@ -1,9 +1,11 @@
-use serde::Deserialize;
+use super::LoadError;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::OnceLock;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
 
 /// Schema for microarchitecture definitions and feature aliases.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct MicroarchitecturesSchema {
     pub microarchitectures: HashMap<String, Microarchitecture>,
     pub feature_aliases: HashMap<String, FeatureAlias>,
@@ -11,20 +13,76 @@ pub struct MicroarchitecturesSchema {
 }
 
 impl MicroarchitecturesSchema {
+    /// Returns the schema of the embedded `microarchitectures.json`, or the one in
+    /// [`super::cpu_dir`], together with the microarchitectures that this crate maintains.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file cannot be loaded, see [`Self::try_load`].
     pub fn schema() -> &'static MicroarchitecturesSchema {
-        static SCHEMA: OnceLock<MicroarchitecturesSchema> = OnceLock::new();
-        SCHEMA.get_or_init(|| {
-            serde_json::from_str(include_str!(concat!(
-                env!("CARGO_MANIFEST_DIR"),
-                "/json/cpu/microarchitectures.json"
-            )))
-            .expect("Failed to load microarchitectures.json")
-        })
+        Self::try_load().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Returns the schema of the embedded `microarchitectures.json`, or the one in
+    /// [`super::cpu_dir`]. The file is only loaded once.
+    pub fn try_load() -> Result<&'static MicroarchitecturesSchema, LoadError> {
+        Self::try_shared().map(|schema| schema.as_ref())
+    }
+
+    /// Returns the schema shared by [`Self::try_load`] and the default target database.
+    pub(crate) fn try_shared() -> Result<&'static Arc<MicroarchitecturesSchema>, LoadError> {
+        static SCHEMA: OnceLock<Result<Arc<MicroarchitecturesSchema>, LoadError>> = OnceLock::new();
+        SCHEMA
+            .get_or_init(|| {
+                let schema = match super::cpu_dir() {
+                    Some(dir) => {
+                        let file = dir.join("microarchitectures.json");
+                        Self::parse(&file, &super::read_file(&file)?)?
+                            .with_extra_microarchitectures()?
+                    }
+                    None => super::embedded::microarchitectures(),
+                };
+                Ok(Arc::new(schema))
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Parses the contents of a `microarchitectures.json` file.
+    pub fn from_json(json: &str) -> Result<Self, LoadError> {
+        Self::parse(Path::new("microarchitectures.json"), json)
+    }
+
+    /// Parses the contents of the given `microarchitectures.json` file, like [`Self::from_json`].
+    pub(crate) fn parse(file: &Path, json: &str) -> Result<Self, LoadError> {
+        super::parse_json(file, json)
+    }
+
+    /// Adds the microarchitectures that this crate maintains but this schema does not define, like
+    /// the default schema of [`Self::try_load`] does.
+    pub fn with_extra_microarchitectures(mut self) -> Result<Self, LoadError> {
+        let extra: ExtraMicroarchitectures = super::parse_json(
+            Path::new("extra_microarchitectures.json"),
+            include_str!("extra_microarchitectures.json"),
+        )?;
+        for (name, microarchitecture) in extra.microarchitectures {
+            self.microarchitectures
+                .entry(name)
+                .or_insert(microarchitecture);
+        }
+        Ok(self)
     }
 }
 
-/// Defines the attributes and requirements of a microarchitecture.
+/// Microarchitectures that are maintained in this crate because they are not (yet) part of the
+/// archspec-json database. Definitions in the database take precedence.
 #[derive(Debug, Deserialize)]
+struct ExtraMicroarchitectures {
+    microarchitectures: HashMap<String, Microarchitecture>,
+}
+
+/// Defines the attributes and requirements of a microarchitecture.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct Microarchitecture {
     /// A list of the immediate microarchitectures that this one is considered
     /// to be derived from.
@@ -48,7 +106,7 @@ pub struct Microarchitecture {
 
 /// Compiler optimization for a particular compiler, either one for all flavours
 /// of the compiler or several indicating how to do it for particular version ranges.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 #[serde(untagged)]
 pub enum CompilerSet {
     /// Multiple entries (Compiler change options across versions).
@@ -60,7 +118,7 @@ pub enum CompilerSet {
 
 /// Indicates how to tell a particular compiler flavour how to optimize
 /// for an microarchitecture.
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Compiler {
     /// Indicates the versions of the compiler this applies to.
     pub versions: String,
@@ -70,11 +128,12 @@ pub struct Compiler {
     pub flags: String,
 
     /// Architecture name, for use in the optimization flags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
 }
 
 /// Synthesised feature aliases derived from existing features or families.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct FeatureAlias {
     /// The reason for why this alias is defined.
     pub reason: Option<String>,
@@ -87,7 +146,7 @@ pub struct FeatureAlias {
 }
 
 /// Conversions that map some platform specific value to canonical values.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Deserialize)]
 pub struct Conversions {
     pub description: String,
 
@@ -3,15 +3,133 @@
 //! These are encoding the rules of the corresponding schema as Rust data types
 //! with the help of `serde` deserialization.
 
-use serde::de;
+use serde::de::{self, DeserializeOwned};
 use serde::{Deserialize, Deserializer};
+use std::fmt::{Display, Formatter};
 use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 mod cpuid;
+mod embedded;
 mod microarchitecture;
+mod validation;
 
 pub use cpuid::*;
 pub use microarchitecture::*;
+pub use validation::ValidationError;
+
+pub(crate) use validation::find_cycle;
+
+/// Environment variable with the path of a directory that contains a `microarchitectures.json`
+/// and `cpuid.json` to use instead of the embedded files, e.g. a newer checkout of archspec-json.
+pub const CPU_DIR_ENV: &str = "ARCHSPEC_CPU_DIR";
+
+static CPU_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Loads the `microarchitectures.json` and `cpuid.json` files from the given directory instead of
+/// using the embedded files. This takes precedence over [`CPU_DIR_ENV`].
+///
+/// The files are only loaded once, so this has to be called before any of them is used. If it is
+/// called too late, the path is returned as an error.
+pub fn set_cpu_dir(path: impl Into<PathBuf>) -> Result<(), PathBuf> {
+    CPU_DIR
+        .set(Some(path.into()))
+        .map_err(|path| path.expect("the path was just set"))
+}
+
+/// Returns the directory the JSON files are loaded from, or `None` if the embedded files are used.
+pub fn cpu_dir() -> Option<&'static Path> {
+    CPU_DIR
+        .get_or_init(|| {
+            std::env::var_os(CPU_DIR_ENV)
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+        })
+        .as_deref()
+}
+
+/// Identifies the archspec-json files that are embedded in this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonVersion {
+    /// The commit of the archspec-json repository the files were taken from, if the crate was
+    /// built from a git checkout of it. Packaged crates, e.g. from crates.io, do not contain the
+    /// git metadata, so this is always `None` for them.
+    pub commit: Option<&'static str>,
+
+    /// A checksum of the contents of the files, including the microarchitectures this crate
+    /// maintains, which differs between versions of them. Unlike [`Self::commit`] it is always
+    /// known, so use it to tell which data a build embeds.
+    pub checksum: &'static str,
+}
+
+/// Returns the version of the embedded archspec-json files. They are not used if a [`cpu_dir`]
+/// is set.
+///
+/// Only [`JsonVersion::checksum`] reliably identifies the files, the commit is missing unless the
+/// crate was built from a git checkout.
+pub fn embedded_json_version() -> JsonVersion {
+    embedded::version()
+}
+
+/// Reads the contents of a schema file.
+pub(crate) fn read_file(path: &Path) -> Result<String, LoadError> {
+    std::fs::read_to_string(path).map_err(|err| LoadError::Io {
+        file: path.to_path_buf(),
+        reason: err.to_string(),
+    })
+}
+
+/// Parses the contents of `file`, reporting where in the document parsing failed.
+fn parse_json<T: DeserializeOwned>(file: &Path, json: &str) -> Result<T, LoadError> {
+    let parse_error = |path: String, err: serde_json::Error| LoadError::Parse {
+        file: file.to_path_buf(),
+        path,
+        reason: err.to_string(),
+    };
+
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let value = serde_path_to_error::deserialize(&mut deserializer)
+        .map_err(|err| parse_error(err.path().to_string(), err.into_inner()))?;
+    deserializer
+        .end()
+        .map_err(|err| parse_error(String::from("."), err))?;
+    Ok(value)
+}
+
+/// Error returned when a schema file cannot be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// The file could not be read.
+    Io { file: PathBuf, reason: String },
+
+    /// The file is not valid JSON or does not match the schema. `path` is the location in the
+    /// document where parsing failed, e.g. `microarchitectures.zen.features[3]`.
+    Parse {
+        file: PathBuf,
+        path: String,
+        reason: String,
+    },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io { file, reason } => {
+                write!(f, "failed to read {}: {}", file.display(), reason)
+            }
+            LoadError::Parse { file, path, reason } => write!(
+                f,
+                "failed to parse {} at '{}': {}",
+                file.display(),
+                path,
+                reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
 
 /// Deserialization helper to map {null, string, [string]} to a sequence of strings.
 fn zero_one_many_string<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
@@ -114,6 +232,48 @@ where
 mod tests {
     use crate::schema::cpuid::CpuIdSchema;
     use crate::schema::microarchitecture::MicroarchitecturesSchema;
+    use crate::schema::LoadError;
+    use std::path::PathBuf;
+
+    #[test]
+    fn set_cpu_dir_after_load() {
+        MicroarchitecturesSchema::schema();
+        assert_eq!(
+            super::set_cpu_dir("/path/to/archspec-json/cpu"),
+            Err("/path/to/archspec-json/cpu".into())
+        );
+    }
+
+    #[test]
+    fn embedded_json_version() {
+        let version = super::embedded_json_version();
+        assert_eq!(version.checksum.len(), 16);
+        assert!(version.checksum.bytes().all(|b| b.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn load_error_path() {
+        let err = MicroarchitecturesSchema::from_json(
+            r#"{
+                "microarchitectures": {
+                    "x86_64": { "from": [], "vendor": "generic", "features": [] },
+                    "zen": { "from": "x86_64", "vendor": "AuthenticAMD", "features": ["sse", 3] }
+                },
+                "feature_aliases": {},
+                "conversions": { "description": "", "arm_vendors": {}, "darwin_flags": {} }
+            }"#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            &err,
+            LoadError::Parse { file, path, .. }
+                if file == &PathBuf::from("microarchitectures.json")
+                    && path == "microarchitectures.zen.features[1]"
+        ));
+
+        let err = CpuIdSchema::from_json("{} {}").unwrap_err();
+        assert!(matches!(&err, LoadError::Parse { path, .. } if path == "."));
+    }
 
     #[test]
     #[ignore]
@@ -0,0 +1,429 @@
+use super::{CompilerSet, Microarchitecture, MicroarchitecturesSchema};
+use itertools::Itertools;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// An inconsistency in a [`MicroarchitecturesSchema`], see [`MicroarchitecturesSchema::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// A parent of a microarchitecture is not defined.
+    UnknownParent { target: String, parent: String },
+
+    /// The microarchitectures derive from each other.
+    Cycle(Vec<String>),
+
+    /// A microarchitecture does not require all the features of one of its parents.
+    MissingParentFeatures {
+        target: String,
+        parent: String,
+        features: Vec<String>,
+    },
+
+    /// A microarchitecture derives from more than one root, so its family is ambiguous.
+    MultipleRoots { target: String, roots: Vec<String> },
+
+    /// A feature alias refers to a feature that no microarchitecture has.
+    UnknownAliasFeature { alias: String, feature: String },
+
+    /// A feature alias refers to a family that is not a root microarchitecture.
+    UnknownAliasFamily { alias: String, family: String },
+
+    /// The versions of a compiler entry are not a `min:max` range of dotted version numbers.
+    InvalidCompilerVersions {
+        target: String,
+        compiler: String,
+        versions: String,
+    },
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::UnknownParent { target, parent } => write!(
+                f,
+                "the parent '{}' of the microarchitecture '{}' is not defined",
+                parent, target
+            ),
+            ValidationError::Cycle(names) => write!(
+                f,
+                "the microarchitectures derive from each other: {}",
+                names.join(" -> ")
+            ),
+            ValidationError::MissingParentFeatures {
+                target,
+                parent,
+                features,
+            } => write!(
+                f,
+                "the microarchitecture '{}' does not have the features {} of its parent '{}'",
+                target,
+                features.join(", "),
+                parent
+            ),
+            ValidationError::MultipleRoots { target, roots } => write!(
+                f,
+                "the microarchitecture '{}' derives from several roots: {}",
+                target,
+                roots.join(", ")
+            ),
+            ValidationError::UnknownAliasFeature { alias, feature } => write!(
+                f,
+                "the feature alias '{}' refers to the unknown feature '{}'",
+                alias, feature
+            ),
+            ValidationError::UnknownAliasFamily { alias, family } => write!(
+                f,
+                "the feature alias '{}' refers to the unknown family '{}'",
+                alias, family
+            ),
+            ValidationError::InvalidCompilerVersions {
+                target,
+                compiler,
+                versions,
+            } => write!(
+                f,
+                "the {} versions '{}' of the microarchitecture '{}' are not a valid range",
+                compiler, versions, target
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl MicroarchitecturesSchema {
+    /// Checks the schema for inconsistencies that would make detection or comparison of the
+    /// microarchitectures unreliable, and returns all of them.
+    ///
+    /// A parent feature counts as present if the child has a feature that the parent's feature is
+    /// an alias of, e.g. `ssse3` for `sse3`.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        let microarchitectures = &self.microarchitectures;
+
+        for (name, target) in microarchitectures.iter().sorted_by_key(|(name, _)| *name) {
+            for parent in &target.from {
+                match microarchitectures.get(parent) {
+                    Some(parent_target) => {
+                        let features = self.missing_parent_features(target, parent_target);
+                        if !features.is_empty() {
+                            errors.push(ValidationError::MissingParentFeatures {
+                                target: name.clone(),
+                                parent: parent.clone(),
+                                features,
+                            });
+                        }
+                    }
+                    None => errors.push(ValidationError::UnknownParent {
+                        target: name.clone(),
+                        parent: parent.clone(),
+                    }),
+                }
+            }
+
+            let roots = roots(name, microarchitectures);
+            if roots.len() > 1 {
+                errors.push(ValidationError::MultipleRoots {
+                    target: name.clone(),
+                    roots: roots.into_iter().map(str::to_string).collect(),
+                });
+            }
+
+            let compilers = target.compilers.iter().flatten();
+            for (compiler, set) in compilers.sorted_by_key(|(compiler, _)| *compiler) {
+                let entries = match set {
+                    CompilerSet::Several(entries) => entries.as_slice(),
+                    CompilerSet::Single(entry) => std::slice::from_ref(entry),
+                };
+                for entry in entries {
+                    if !is_version_range(&entry.versions) {
+                        errors.push(ValidationError::InvalidCompilerVersions {
+                            target: name.clone(),
+                            compiler: compiler.clone(),
+                            versions: entry.versions.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some(cycle) = find_cycle(microarchitectures) {
+            errors.push(ValidationError::Cycle(cycle));
+        }
+
+        let features: HashSet<&str> = microarchitectures
+            .values()
+            .flat_map(|target| target.features.iter().map(String::as_str))
+            .collect();
+        for (alias, definition) in self
+            .feature_aliases
+            .iter()
+            .sorted_by_key(|(alias, _)| *alias)
+        {
+            for feature in definition.any_of.iter().flatten() {
+                if !features.contains(feature.as_str())
+                    && !self.feature_aliases.contains_key(feature)
+                {
+                    errors.push(ValidationError::UnknownAliasFeature {
+                        alias: alias.clone(),
+                        feature: feature.clone(),
+                    });
+                }
+            }
+            for family in definition.families.iter().flatten() {
+                if !microarchitectures
+                    .get(family)
+                    .is_some_and(|target| target.from.is_empty())
+                {
+                    errors.push(ValidationError::UnknownAliasFamily {
+                        alias: alias.clone(),
+                        family: family.clone(),
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Returns the features of `parent` that `target` does not have, taking aliases into account.
+    fn missing_parent_features(
+        &self,
+        target: &Microarchitecture,
+        parent: &Microarchitecture,
+    ) -> Vec<String> {
+        parent
+            .features
+            .iter()
+            .filter(|feature| {
+                let aliased = self
+                    .feature_aliases
+                    .get(*feature)
+                    .and_then(|alias| alias.any_of.as_ref())
+                    .is_some_and(|any_of| any_of.iter().any(|f| target.features.contains(f)));
+                !target.features.contains(feature) && !aliased
+            })
+            .cloned()
+            .sorted()
+            .collect()
+    }
+}
+
+/// Returns the names of the microarchitectures without parents that `name` derives from, or
+/// `name` itself if it has none. Parents that are not defined are ignored.
+fn roots<'a>(
+    name: &'a str,
+    microarchitectures: &'a HashMap<String, Microarchitecture>,
+) -> BTreeSet<&'a str> {
+    let mut roots = BTreeSet::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![name];
+    while let Some(name) = pending.pop() {
+        let Some((name, target)) = microarchitectures.get_key_value(name) else {
+            continue;
+        };
+        if !seen.insert(name.as_str()) {
+            continue;
+        }
+        if target.from.is_empty() {
+            roots.insert(name.as_str());
+        }
+        pending.extend(target.from.iter().map(String::as_str));
+    }
+    roots
+}
+
+/// Returns true if `versions` is a `min:max` range of dotted version numbers, where either bound
+/// may be omitted.
+fn is_version_range(versions: &str) -> bool {
+    fn parse(version: &str) -> Option<Vec<u64>> {
+        if version.is_empty() {
+            return Some(Vec::new());
+        }
+        version
+            .split('.')
+            .map(|component| {
+                component
+                    .bytes()
+                    .all(|b| b.is_ascii_digit())
+                    .then(|| component.parse().ok())
+                    .flatten()
+            })
+            .collect()
+    }
+
+    let Some((min, max)) = versions.split_once(':') else {
+        return false;
+    };
+    match (parse(min), parse(max)) {
+        (Some(min), Some(max)) => min.is_empty() || max.is_empty() || min <= max,
+        _ => false,
+    }
+}
+
+/// Returns the names of microarchitectures that derive from each other, if any. Parents that are
+/// not part of `definitions` are ignored.
+pub(crate) fn find_cycle(definitions: &HashMap<String, Microarchitecture>) -> Option<Vec<String>> {
+    fn visit<'a>(
+        name: &'a str,
+        definitions: &'a HashMap<String, Microarchitecture>,
+        path: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = path.iter().position(|n| *n == name) {
+            let mut cycle = path[start..].iter().map(|n| n.to_string()).collect_vec();
+            cycle.push(name.to_string());
+            return Some(cycle);
+        }
+        if done.contains(name) {
+            return None;
+        }
+        let (name, definition) = definitions.get_key_value(name)?;
+        path.push(name);
+        for parent in &definition.from {
+            if let Some(cycle) = visit(parent, definitions, path, done) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        done.insert(name);
+        None
+    }
+
+    let mut done = HashSet::new();
+    definitions
+        .keys()
+        .sorted()
+        .find_map(|name| visit(name, definitions, &mut Vec::new(), &mut done))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_version_range, ValidationError};
+    use crate::schema::MicroarchitecturesSchema;
+
+    fn validate(microarchitectures: &str) -> Vec<ValidationError> {
+        let json = format!(
+            r#"{{
+                "microarchitectures": {},
+                "feature_aliases": {{
+                    "sse3": {{ "any_of": ["ssse3"] }},
+                    "neon": {{ "families": ["aarch64"] }},
+                    "avx512": {{ "any_of": ["avx512f", "avx512_6"] }}
+                }},
+                "conversions": {{ "description": "", "arm_vendors": {{}}, "darwin_flags": {{}} }}
+            }}"#,
+            microarchitectures
+        );
+        let schema: MicroarchitecturesSchema = serde_json::from_str(&json).unwrap();
+        schema.validate().err().unwrap_or_default()
+    }
+
+    #[test]
+    fn test_validate_known_schema() {
+        // The upstream data has a few known inconsistencies, listed here so that new ones are
+        // noticed. Most are x86 features only listed on the generic levels, which cannot simply
+        // be added to the specific microarchitectures because macOS does not report e.g.
+        // `lahf_lm`, so `haswell` would no longer be detected there. `icelake` calls the `sha`
+        // feature of `cannonlake` `sha_ni`, and `zen5` lacks the `pku` feature of `zen4`.
+        let errors = MicroarchitecturesSchema::schema()
+            .validate()
+            .err()
+            .unwrap_or_default();
+        let missing = errors
+            .iter()
+            .map(|err| match err {
+                ValidationError::MissingParentFeatures { target, parent, .. } => {
+                    (target.as_str(), parent.as_str())
+                }
+                err => panic!("unexpected validation error: {}", err),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            missing,
+            vec![
+                ("bulldozer", "x86_64_v2"),
+                ("excavator", "x86_64_v3"),
+                ("haswell", "x86_64_v3"),
+                ("icelake", "cannonlake"),
+                ("nehalem", "x86_64_v2"),
+                ("skylake_avx512", "x86_64_v4"),
+                ("zen", "x86_64_v3"),
+                ("zen4", "x86_64_v4"),
+                ("zen5", "zen4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let errors = validate(
+            r#"{
+                "x86_64": { "from": [], "vendor": "generic", "features": ["avx512f"] },
+                "aarch64": { "from": [], "vendor": "generic", "features": [] },
+                "core2": { "from": "x86_64", "vendor": "GenuineIntel", "features": ["ssse3", "avx512f"],
+                    "compilers": { "gcc": [{ "versions": "4.3:", "flags": "" }, { "versions": "4.x:", "flags": "" }] } },
+                "mixed": { "from": ["x86_64", "aarch64"], "vendor": "generic", "features": ["avx512f"] },
+                "orphan": { "from": "unknown", "vendor": "generic", "features": [] },
+                "loop_a": { "from": "loop_b", "vendor": "generic", "features": [] },
+                "loop_b": { "from": "loop_a", "vendor": "generic", "features": [] },
+                "nehalem": { "from": "core2", "vendor": "GenuineIntel", "features": ["sse3", "popcnt"],
+                    "compilers": { "clang": { "versions": "12.0:3.9", "flags": "" } } }
+            }"#,
+        );
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::InvalidCompilerVersions {
+                    target: "core2".to_string(),
+                    compiler: "gcc".to_string(),
+                    versions: "4.x:".to_string()
+                },
+                ValidationError::MultipleRoots {
+                    target: "mixed".to_string(),
+                    roots: vec!["aarch64".to_string(), "x86_64".to_string()]
+                },
+                ValidationError::MissingParentFeatures {
+                    target: "nehalem".to_string(),
+                    parent: "core2".to_string(),
+                    features: vec!["avx512f".to_string(), "ssse3".to_string()]
+                },
+                ValidationError::InvalidCompilerVersions {
+                    target: "nehalem".to_string(),
+                    compiler: "clang".to_string(),
+                    versions: "12.0:3.9".to_string()
+                },
+                ValidationError::UnknownParent {
+                    target: "orphan".to_string(),
+                    parent: "unknown".to_string()
+                },
+                ValidationError::Cycle(vec![
+                    "loop_a".to_string(),
+                    "loop_b".to_string(),
+                    "loop_a".to_string()
+                ]),
+                ValidationError::UnknownAliasFeature {
+                    alias: "avx512".to_string(),
+                    feature: "avx512_6".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_version_range() {
+        assert!(is_version_range(":"));
+        assert!(is_version_range("4.9:"));
+        assert!(is_version_range(":2021.2"));
+        assert!(is_version_range("10.0.0:10.0.99"));
+        assert!(is_version_range("4.6:11.0"));
+        assert!(!is_version_range("4.9"));
+        assert!(!is_version_range("4..9:"));
+        assert!(!is_version_range("11:4"));
+        assert!(!is_version_range("4.9:5.0:6.0"));
+    }
+}